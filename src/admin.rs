@@ -0,0 +1,179 @@
+use scraper::Selector;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use worker::*;
+
+use crate::source::{self, QuoteSource};
+use crate::selector_store::{missing_required_fields, SelectorSet, SelectorStore, KV_BINDING};
+use crate::PageType;
+
+// --- 管理API (ADMIN_KEY で保護) ---
+//   GET    /admin/selectors                     保存済みセレクターセットの一覧
+//   GET    /admin/selectors/:page_type          1件表示
+//   PUT    /admin/selectors/:page_type          置き換え
+//   DELETE /admin/selectors/:page_type          削除 (次回の /quote で再探索される)
+//   POST   /admin/selectors/:page_type/pin      ピン留め (自動学習で上書きされなくなる)
+//   DELETE /admin/selectors/:page_type/pin      ピン留め解除
+
+pub const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+// `wrangler secret put ADMIN_KEY` で設定する (ローカルでは .dev.vars)。[vars] には書かない
+const ADMIN_KEY_SECRET: &str = "ADMIN_KEY";
+// 以前 wrangler.toml と api.http に載せていた例の値。これが設定されたままなら無効扱いにする
+const PLACEHOLDER_ADMIN_KEY: &str = "secret-admin-key";
+
+#[derive(Deserialize, Debug)]
+struct SelectorSetUpdate {
+    fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pinned: bool,
+}
+
+// キーの比較で処理時間から一致文字数が推測されないよう、常に全体を比較する
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 未設定・空・例の値のままなら None (管理APIを無効にする)
+fn configured_key(value: Option<String>) -> Option<String> {
+    value.filter(|key| !key.trim().is_empty() && key != PLACEHOLDER_ADMIN_KEY)
+}
+
+/// ヘッダーのキーを検証し、拒否する場合はそのレスポンスを返す
+fn reject_unauthorized(req: &Request, ctx: &RouteContext<()>) -> Result<Option<Response>> {
+    let Some(expected) = configured_key(ctx.secret(ADMIN_KEY_SECRET).ok().map(|s| s.to_string())) else {
+        return Response::error("Admin API is disabled: set the ADMIN_KEY secret with `wrangler secret put ADMIN_KEY`", 503).map(Some);
+    };
+    match req.headers().get(ADMIN_KEY_HEADER)? {
+        None => Response::error(format!("Missing {} header", ADMIN_KEY_HEADER), 401).map(Some),
+        Some(key) if !constant_time_eq(&key, &expected) => Response::error("Invalid admin key", 403).map(Some),
+        Some(_) => Ok(None),
+    }
+}
+
+fn selector_store(ctx: &RouteContext<()>) -> Result<SelectorStore> {
+    Ok(SelectorStore::new(ctx.kv(KV_BINDING)?, source::default_source().name()))
+}
+
+fn page_type_param(ctx: &RouteContext<()>) -> Option<PageType> {
+    ctx.param("page_type").and_then(|name| PageType::from_name(name))
+}
+
+fn unknown_page_type() -> Result<Response> {
+    let names: Vec<&str> = PageType::ALL.iter().map(|t| t.as_str()).collect();
+    Response::error(format!("Unknown page type. Expected one of: {}", names.join(", ")), 404)
+}
+
+pub async fn list_selector_sets(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(denied) = reject_unauthorized(&req, &ctx)? {
+        return Ok(denied);
+    }
+    let sets = selector_store(&ctx)?.list().await;
+    Response::from_json(&sets)
+}
+
+pub async fn get_selector_set(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(denied) = reject_unauthorized(&req, &ctx)? {
+        return Ok(denied);
+    }
+    let Some(page_type) = page_type_param(&ctx) else {
+        return unknown_page_type();
+    };
+    match selector_store(&ctx)?.load(page_type).await {
+        Some(set) => Response::from_json(&set),
+        None => Response::error(format!("No selector set stored for '{}'", page_type.as_str()), 404),
+    }
+}
+
+pub async fn put_selector_set(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(denied) = reject_unauthorized(&req, &ctx)? {
+        return Ok(denied);
+    }
+    let Some(page_type) = page_type_param(&ctx) else {
+        return unknown_page_type();
+    };
+    let update: SelectorSetUpdate = match req.json().await {
+        Ok(u) => u,
+        Err(e) => return Response::error(format!("Invalid request body: {}", e), 400),
+    };
+
+    // 必須フィールドが欠けたセットは /quote で必ず外れて探索にフォールバックするだけなので受け付けない
+    let missing = missing_required_fields(&update.fields);
+    if !missing.is_empty() {
+        return Response::error(format!("Missing required fields: {}", missing.join(", ")), 400);
+    }
+
+    // 壊れたセレクターを保存すると /quote が毎回探索にフォールバックするので、保存前に構文を検証する
+    let invalid: Vec<String> = update
+        .fields
+        .iter()
+        .filter(|(_, sel)| Selector::parse(sel).is_err())
+        .map(|(field, sel)| format!("{}: {}", field, sel))
+        .collect();
+    if !invalid.is_empty() {
+        return Response::error(format!("Invalid selectors: {}", invalid.join(", ")), 400);
+    }
+
+    let mut set = SelectorSet::new(page_type, update.fields);
+    set.pinned = update.pinned;
+    selector_store(&ctx)?.save(&set).await?;
+    Response::from_json(&set)
+}
+
+pub async fn delete_selector_set(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(denied) = reject_unauthorized(&req, &ctx)? {
+        return Ok(denied);
+    }
+    let Some(page_type) = page_type_param(&ctx) else {
+        return unknown_page_type();
+    };
+    selector_store(&ctx)?.delete(page_type).await?;
+    Ok(Response::empty()?.with_status(204))
+}
+
+pub async fn pin_selector_set(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    set_pinned(req, ctx, true).await
+}
+
+pub async fn unpin_selector_set(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    set_pinned(req, ctx, false).await
+}
+
+async fn set_pinned(req: Request, ctx: RouteContext<()>, pinned: bool) -> Result<Response> {
+    if let Some(denied) = reject_unauthorized(&req, &ctx)? {
+        return Ok(denied);
+    }
+    let Some(page_type) = page_type_param(&ctx) else {
+        return unknown_page_type();
+    };
+    let store = selector_store(&ctx)?;
+    let Some(mut set) = store.load(page_type).await else {
+        return Response::error(format!("No selector set stored for '{}'", page_type.as_str()), 404);
+    };
+    set.pinned = pinned;
+    store.save(&set).await?;
+    Response::from_json(&set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholder_and_empty_keys_disable_the_api() {
+        assert_eq!(configured_key(None), None);
+        assert_eq!(configured_key(Some("  ".to_string())), None);
+        assert_eq!(configured_key(Some(PLACEHOLDER_ADMIN_KEY.to_string())), None);
+        assert_eq!(configured_key(Some("k3y".to_string())).as_deref(), Some("k3y"));
+    }
+
+    #[test]
+    fn keys_are_compared_whole() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+}
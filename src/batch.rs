@@ -0,0 +1,315 @@
+use futures::future::{self, Either};
+use futures::stream::{self, StreamExt};
+use std::cell::Cell;
+use std::future::Future;
+use std::time::Duration;
+use worker::*;
+
+// --- 複数コードの一括取得 ---
+// 同時実行数を制限しつつ並行に取得し、結果は入力と同じ順序で返す。
+// Workers は同時に開ける接続数が 6 本までなので、それを超えて並べても待たされるだけになる。
+// 1リクエストあたりのサブリクエスト数 (ページ取得・KV・Cache API) にも上限 (無料プランで 50) があるので、
+// キャッシュにあるコードを先に返し、残りを予算の範囲で取得する。予算を超えた分はコードごとに「スキップ」のエラーにする。
+
+const CONCURRENCY_VAR: &str = "QUOTE_CONCURRENCY";
+const TIMEOUT_VAR: &str = "QUOTE_TIMEOUT_MS";
+const MAX_SUBREQUESTS_VAR: &str = "QUOTE_MAX_SUBREQUESTS";
+
+const DEFAULT_CONCURRENCY: usize = 6;
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+// 無料プランの上限。有料プランでは 1000 まで上げられる
+const DEFAULT_MAX_SUBREQUESTS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchConfig {
+    pub concurrency: usize,
+    // コード1件あたりの制限時間。None なら無制限
+    pub timeout: Option<Duration>,
+    // 1リクエストで使うサブリクエストの上限。None なら無制限
+    pub max_subrequests: Option<usize>,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            concurrency: DEFAULT_CONCURRENCY,
+            timeout: Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)),
+            max_subrequests: Some(DEFAULT_MAX_SUBREQUESTS),
+        }
+    }
+}
+
+impl BatchConfig {
+    // wrangler.toml の [vars] から読み込む。未設定や不正な値は既定値を使う
+    pub fn from_ctx(ctx: &RouteContext<()>) -> Self {
+        let read = |name: &str| ctx.var(name).ok().and_then(|v| v.to_string().trim().parse::<u64>().ok());
+        let defaults = BatchConfig::default();
+        BatchConfig {
+            concurrency: read(CONCURRENCY_VAR).map_or(defaults.concurrency, |n| (n as usize).max(1)),
+            timeout: match read(TIMEOUT_VAR) {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => defaults.timeout,
+            },
+            max_subrequests: match read(MAX_SUBREQUESTS_VAR) {
+                Some(0) => None,
+                Some(n) => Some(n as usize),
+                None => defaults.max_subrequests,
+            },
+        }
+    }
+
+    // リクエスト1回分の予算
+    pub fn budget(&self) -> SubrequestBudget {
+        SubrequestBudget { remaining: Cell::new(self.max_subrequests) }
+    }
+}
+
+/// 1リクエストで使えるサブリクエストの残り回数。None なら無制限
+#[derive(Debug)]
+pub struct SubrequestBudget {
+    remaining: Cell<Option<usize>>,
+}
+
+impl SubrequestBudget {
+    /// count 回分が残っていれば差し引いて true。足りなければ何も差し引かずに false
+    pub fn take(&self, count: usize) -> bool {
+        match self.remaining.get() {
+            None => true,
+            Some(left) if left >= count => {
+                self.remaining.set(Some(left - count));
+                true
+            }
+            Some(_) => false,
+        }
+    }
+}
+
+// 予算を使い切って取得しなかったコードのエラー
+fn skipped() -> Error {
+    Error::from("Skipped: subrequest limit for this request reached; retry this code in a separate request")
+}
+
+/// items を最大 concurrency 件ずつ並行に処理する。結果は items と同じ順序。
+/// 完了した順に次の件を始めるので、遅い1件が後続を待たせることはない
+pub async fn run_batch<I, T, F, Fut>(items: Vec<I>, config: &BatchConfig, task: F) -> Vec<Result<T>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let timeout = config.timeout;
+    let mut results: Vec<(usize, Result<T>)> = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| {
+            let task = with_timeout(task(item), timeout);
+            async move { (index, task.await) }
+        })
+        .buffer_unordered(config.concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// 各項目をキャッシュから引く (1件につき cost 回のサブリクエスト)。予算が尽きた後の項目は引かずに None
+pub async fn lookup_all<I, T, F, Fut>(items: Vec<I>, config: &BatchConfig, budget: &SubrequestBudget, cost: usize, lookup: F) -> Vec<Option<T>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let lookup = &lookup;
+    run_batch(items, config, |item| async move { Ok(if budget.take(cost) { lookup(item).await } else { None }) })
+        .await
+        .into_iter()
+        .map(|result| result.ok().flatten())
+        .collect()
+}
+
+/// 1件につき cost 回のサブリクエストを予算から確保して task を実行する。
+/// 確保できなかった項目は実行せず、その項目だけ「スキップ」のエラーにする (先頭の項目から順に確保する)
+pub async fn run_budgeted<I, T, F, Fut>(items: Vec<I>, config: &BatchConfig, budget: &SubrequestBudget, cost: usize, task: F) -> Vec<Result<T>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let task = &task;
+    run_batch(items, config, |item| async move {
+        if budget.take(cost) {
+            task(item).await
+        } else {
+            Err(skipped())
+        }
+    })
+    .await
+}
+
+/// lookup_all の結果の空き (None) に、残りの項目を run_budgeted で処理した結果を順に詰める
+pub fn merge_in_order<T>(cached: Vec<Option<T>>, fetched: Vec<Result<T>>) -> Vec<Result<T>> {
+    let mut fetched = fetched.into_iter();
+    cached
+        .into_iter()
+        .map(|hit| match hit {
+            Some(value) => Ok(value),
+            None => fetched.next().unwrap_or_else(|| Err(skipped())),
+        })
+        .collect()
+}
+
+async fn with_timeout<T>(task: impl Future<Output = Result<T>>, timeout: Option<Duration>) -> Result<T> {
+    let Some(limit) = timeout else {
+        return task.await;
+    };
+    let task = std::pin::pin!(task);
+    match future::select(task, Delay::from(limit)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Error::from(format!("Timed out after {}ms", limit.as_millis()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::{Cell, RefCell};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // 1回だけ Pending を返して他のタスクに順番を譲る
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn config(concurrency: usize) -> BatchConfig {
+        BatchConfig { concurrency, timeout: None, max_subrequests: None }
+    }
+
+    #[test]
+    fn results_keep_input_order() {
+        let items = vec![3u32, 1, 2, 5, 4];
+        let results = block_on(run_batch(items, &config(3), |n| async move {
+            // 大きい数ほど長く待たせ、完了順を入力順とずらす
+            for _ in 0..n {
+                YieldNow(false).await;
+            }
+            Ok(n * 10)
+        }));
+        let values: Vec<u32> = results.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(values, vec![30, 10, 20, 50, 40]);
+    }
+
+    #[test]
+    fn concurrency_is_bounded() {
+        let in_flight = Cell::new(0usize);
+        let peak = Cell::new(0usize);
+        let items: Vec<u32> = (0..10).collect();
+        block_on(run_batch(items, &config(3), |_| async {
+            in_flight.set(in_flight.get() + 1);
+            peak.set(peak.get().max(in_flight.get()));
+            YieldNow(false).await;
+            in_flight.set(in_flight.get() - 1);
+            Ok(())
+        }));
+        assert_eq!(peak.get(), 3);
+    }
+
+    #[test]
+    fn errors_are_reported_per_item() {
+        let items = vec!["7203.T", "", "6758.T"];
+        let results = block_on(run_batch(items, &config(2), |code| async move {
+            if code.is_empty() {
+                Err(Error::from("empty code"))
+            } else {
+                Ok(code.to_string())
+            }
+        }));
+        assert_eq!(results[0].as_ref().unwrap(), "7203.T");
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(), "empty code");
+        assert_eq!(results[2].as_ref().unwrap(), "6758.T");
+    }
+
+    #[test]
+    fn slow_items_do_not_hold_back_the_rest() {
+        let finished = RefCell::new(Vec::new());
+        let items = vec![20u32, 1, 1, 1];
+        let results = block_on(run_batch(items, &config(2), |n| {
+            let finished = &finished;
+            async move {
+                for _ in 0..n {
+                    YieldNow(false).await;
+                }
+                finished.borrow_mut().push(n);
+                Ok(n)
+            }
+        }));
+        // 先頭の遅い1件を待たずに、空いた枠で残りを処理している
+        assert_eq!(*finished.borrow(), vec![1, 1, 1, 20]);
+        assert_eq!(results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(), vec![20, 1, 1, 1]);
+    }
+
+    #[test]
+    fn budget_is_not_overdrawn() {
+        let budget = BatchConfig { max_subrequests: Some(5), ..config(1) }.budget();
+        assert!(budget.take(2));
+        assert!(budget.take(2));
+        // 残り 1 回では 2 回分を確保できず、残りもそのまま
+        assert!(!budget.take(2));
+        assert!(budget.take(1));
+        assert!(!budget.take(1));
+        assert!(config(1).budget().take(usize::MAX));
+        assert_eq!(BatchConfig::default().max_subrequests, Some(DEFAULT_MAX_SUBREQUESTS));
+    }
+
+    #[test]
+    fn thirty_codes_get_thirty_results() {
+        let codes: Vec<String> = (0..30).map(|i| format!("{}.T", 1000 + i)).collect();
+        // 3件に1件はキャッシュにある。キャッシュ読み 30 回 + 取得 (1件 2 回) 5 件分の予算
+        let batch = BatchConfig { max_subrequests: Some(40), ..config(4) };
+        let budget = batch.budget();
+        let cached = block_on(lookup_all(codes.clone(), &batch, &budget, 1, |code| async move {
+            let n: u32 = code.trim_end_matches(".T").parse().unwrap();
+            n.is_multiple_of(3).then(|| format!("cached {}", code))
+        }));
+        let misses: Vec<String> = codes.iter().zip(&cached).filter(|(_, hit)| hit.is_none()).map(|(code, _)| code.clone()).collect();
+        assert_eq!(misses.len(), 20);
+        let fetched = block_on(run_budgeted(misses, &batch, &budget, 2, |code| async move { Ok(format!("fetched {}", code)) }));
+        let results = merge_in_order(cached, fetched);
+
+        assert_eq!(results.len(), 30);
+        // 入力と同じ順序で、キャッシュ済みは全件、未取得は予算の分だけ先頭から取得し、残りはコードごとにスキップ
+        assert_eq!(results[0].as_ref().unwrap(), "fetched 1000.T");
+        assert_eq!(results[2].as_ref().unwrap(), "cached 1002.T");
+        assert_eq!(results.iter().filter(|r| r.as_ref().is_ok_and(|v| v.starts_with("cached"))).count(), 10);
+        assert_eq!(results.iter().filter(|r| r.as_ref().is_ok_and(|v| v.starts_with("fetched"))).count(), 5);
+        let skipped: Vec<_> = results.iter().filter_map(|r| r.as_ref().err()).collect();
+        assert_eq!(skipped.len(), 15);
+        assert!(skipped[0].to_string().starts_with("Skipped:"));
+        assert_eq!(results[29].as_ref().unwrap(), "cached 1029.T");
+    }
+
+    #[test]
+    fn unlimited_budget_fetches_everything() {
+        let codes: Vec<u32> = (0..30).collect();
+        let batch = config(6);
+        let budget = batch.budget();
+        let cached = block_on(lookup_all(codes.clone(), &batch, &budget, 1, |_| async { None::<u32> }));
+        let fetched = block_on(run_budgeted(codes, &batch, &budget, 2, |n| async move { Ok(n) }));
+        let results: Vec<u32> = merge_in_order(cached, fetched).into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(results, (0..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn zero_concurrency_still_makes_progress() {
+        let results = block_on(run_batch(vec![1, 2], &config(0), |n| async move { Ok(n) }));
+        assert_eq!(results.len(), 2);
+    }
+}
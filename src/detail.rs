@@ -0,0 +1,187 @@
+use regex::Regex;
+use rust_decimal::Decimal;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use worker::*;
+
+use crate::parsing::{parse_decimal, to_half_width};
+use crate::profile::{self, ExtractionProfile};
+
+// --- 詳細欄の項目 (前日終値・始値・高値・安値・出来高・売買代金・年初来高値/安値など) ---
+// profiles/detail.json のラベルで値を探し、"6,245,778株(19:59)" のような表示を数値・単位・注記に分ける。
+// /quote?detail=1 または /quote?fields=open,high のときだけ取り出す。
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DetailField {
+    PreviousClose,
+    Open,
+    High,
+    Low,
+    Volume,
+    TradingValue,
+    YearHigh,
+    YearLow,
+    Week52High,
+    Week52Low,
+}
+
+impl DetailField {
+    pub const ALL: [DetailField; 10] = [
+        DetailField::PreviousClose,
+        DetailField::Open,
+        DetailField::High,
+        DetailField::Low,
+        DetailField::Volume,
+        DetailField::TradingValue,
+        DetailField::YearHigh,
+        DetailField::YearLow,
+        DetailField::Week52High,
+        DetailField::Week52Low,
+    ];
+
+    // profiles/detail.json のフィールド名と同じ
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DetailField::PreviousClose => "previous_close",
+            DetailField::Open => "open",
+            DetailField::High => "high",
+            DetailField::Low => "low",
+            DetailField::Volume => "volume",
+            DetailField::TradingValue => "trading_value",
+            DetailField::YearHigh => "year_high",
+            DetailField::YearLow => "year_low",
+            DetailField::Week52High => "week52_high",
+            DetailField::Week52Low => "week52_low",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DetailValue {
+    // ページの表示そのまま
+    pub display: String,
+    // 表示どおりの数値 (単位の換算はしない)。"---" などは None
+    pub value: Option<Decimal>,
+    // 数値の後ろの単位 ("株" "千" "千ドル" など)
+    pub unit: Option<String>,
+    // 括弧内の注記 (時刻や日付)
+    pub note: Option<String>,
+}
+
+pub type QuoteDetail = BTreeMap<DetailField, DetailValue>;
+
+/// "fields=open,high,volume" を解釈する。知らない名前があればその名前を返す
+pub fn parse_fields(list: &str) -> std::result::Result<Vec<DetailField>, String> {
+    let mut fields = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let field = DetailField::from_name(name).ok_or_else(|| name.to_string())?;
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+    Ok(fields)
+}
+
+/// ページから詳細欄の項目を取り出す。見つからない項目は結果に含めない
+pub fn extract_detail(document: &Html, fields: &[DetailField]) -> Result<QuoteDetail> {
+    let mut profile = ExtractionProfile::detail()?;
+    profile.fields.retain(|rule| fields.iter().any(|f| f.as_str() == rule.field));
+    let extraction = profile::extract(document, &profile)?;
+    Ok(fields
+        .iter()
+        .filter_map(|field| {
+            let raw = extraction.fields.get(field.as_str())?;
+            Some((*field, parse_detail_value(raw)))
+        })
+        .collect())
+}
+
+fn detail_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // 数値 (または "---") + 単位 + (注記)
+    RE.get_or_init(|| Regex::new(r"^\s*([^(（]*?)\s*[(（]([^)）]*)[)）]\s*$").unwrap())
+}
+
+fn number_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^([+\-]?[\d,]+(?:\.\d+)?)\s*(.*)$").unwrap())
+}
+
+pub fn parse_detail_value(raw: &str) -> DetailValue {
+    let display = raw.trim().to_string();
+    let text = to_half_width(&display);
+    let (body, note) = match detail_regex().captures(&text) {
+        Some(caps) => (caps[1].to_string(), Some(caps[2].trim().to_string())),
+        None => (text.trim().to_string(), None),
+    };
+    // "--:--" "----/--" は値が出ていないときの表示
+    let note = note.filter(|n| n.chars().any(|c| c.is_ascii_digit()));
+    let (value, unit) = match number_regex().captures(&body) {
+        Some(caps) => (parse_decimal(&caps[1]), Some(caps[2].trim().to_string()).filter(|u| !u.is_empty())),
+        None => (None, None),
+    };
+    DetailValue { display, value, unit, note }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> Option<Decimal> {
+        Some(Decimal::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn display_values_are_split_into_parts() {
+        let v = parse_detail_value("6,245,778株(19:59)");
+        assert_eq!((v.value, v.unit.as_deref(), v.note.as_deref()), (dec("6245778"), Some("株"), Some("19:59")));
+        let v = parse_detail_value("28.18(10/29)");
+        assert_eq!((v.value, v.unit, v.note.as_deref()), (dec("28.18"), None, Some("10/29")));
+        let v = parse_detail_value("47,632.00");
+        assert_eq!((v.value, v.unit, v.note), (dec("47632.00"), None, None));
+        let v = parse_detail_value("---(--:--)");
+        assert_eq!((v.value, v.unit, v.note), (None, None, None));
+        assert_eq!(v.display, "---(--:--)");
+    }
+
+    #[test]
+    fn fields_parameter() {
+        assert_eq!(parse_fields("open, high,open"), Ok(vec![DetailField::Open, DetailField::High]));
+        assert_eq!(parse_fields("open,close"), Err("close".to_string()));
+        assert_eq!(parse_fields(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn us_stock_fixture() {
+        let document = Html::parse_document(include_str!("../sample.html"));
+        let detail = extract_detail(&document, &DetailField::ALL).unwrap();
+        let value = |f: DetailField| detail.get(&f).and_then(|v| v.value);
+        assert_eq!(value(DetailField::PreviousClose), dec("28.18"));
+        assert_eq!(value(DetailField::Open), dec("27.60"));
+        assert_eq!(value(DetailField::High), dec("27.90"));
+        assert_eq!(value(DetailField::Low), dec("27.55"));
+        assert_eq!(value(DetailField::Volume), dec("6245778"));
+        assert_eq!(detail[&DetailField::TradingValue].unit.as_deref(), Some("千"));
+        assert_eq!(value(DetailField::Week52High), dec("30.29"));
+        assert_eq!(detail[&DetailField::Week52Low].note.as_deref(), Some("24/10/31"));
+        // 米国株のページには年初来高値/安値の欄がない
+        assert!(!detail.contains_key(&DetailField::YearHigh));
+    }
+
+    #[test]
+    fn index_fixture() {
+        let document = Html::parse_document(include_str!("../DJI.html"));
+        let detail = extract_detail(&document, &[DetailField::Open, DetailField::Volume]).unwrap();
+        assert_eq!(detail.len(), 2);
+        assert_eq!(detail[&DetailField::Open].value, dec("47446.88"));
+        assert_eq!(detail[&DetailField::Volume].value, dec("614874963"));
+    }
+}
@@ -0,0 +1,174 @@
+use rust_decimal::Decimal;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use worker::*;
+
+use crate::detail::parse_detail_value;
+use crate::parsing::parse_unit;
+use crate::profile::{self, ExtractionProfile};
+use crate::source::QuoteSource;
+
+// --- 指標欄 (時価総額・PER・PBR・EPS/BPS・配当利回り・単元株数) ---
+// 指標欄は「時価総額」「PER」などの行が並ぶだけで、項目ごとに安定したクラスがなく行の順番もページ種別で変わる。
+// CSS セレクターでは項目名で行を選べないので、profiles/fundamentals.json はラベルだけで値を探す
+// (FieldRule に selectors を書けばラベルより先に試される)。
+// "52,123,456百万円" "12.34倍" のような表示を単位の倍数を掛けた数値にそろえる。
+// スクリーニング用に /fundamentals?code=... で返す。
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FundamentalField {
+    MarketCap,
+    SharesOutstanding,
+    Per,
+    Pbr,
+    Eps,
+    Bps,
+    DividendYield,
+    UnitShares,
+}
+
+impl FundamentalField {
+    pub const ALL: [FundamentalField; 8] = [
+        FundamentalField::MarketCap,
+        FundamentalField::SharesOutstanding,
+        FundamentalField::Per,
+        FundamentalField::Pbr,
+        FundamentalField::Eps,
+        FundamentalField::Bps,
+        FundamentalField::DividendYield,
+        FundamentalField::UnitShares,
+    ];
+
+    // profiles/fundamentals.json のフィールド名と同じ
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FundamentalField::MarketCap => "market_cap",
+            FundamentalField::SharesOutstanding => "shares_outstanding",
+            FundamentalField::Per => "per",
+            FundamentalField::Pbr => "pbr",
+            FundamentalField::Eps => "eps",
+            FundamentalField::Bps => "bps",
+            FundamentalField::DividendYield => "dividend_yield",
+            FundamentalField::UnitShares => "unit_shares",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FundamentalValue {
+    // ページの表示そのまま
+    pub display: String,
+    // 単位の倍数を掛けた数値 ("165,532,028.00千ドル" => 165532028000)。"---" や知らない単位は None
+    pub value: Option<Decimal>,
+    // 正規化した単位 ("JPY" "USD" "shares" "times" "percent")
+    pub unit: Option<String>,
+    // 括弧内の注記 (時刻や日付)
+    pub note: Option<String>,
+}
+
+pub type Fundamentals = BTreeMap<FundamentalField, FundamentalValue>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FundamentalsData {
+    pub code: String,
+    pub fundamentals: Fundamentals,
+}
+
+/// ページから指標欄の項目を取り出す。見つからない項目は結果に含めない
+pub fn extract_fundamentals(document: &Html) -> Result<Fundamentals> {
+    let profile = ExtractionProfile::fundamentals()?;
+    let extraction = profile::extract(document, &profile)?;
+    Ok(FundamentalField::ALL
+        .iter()
+        .filter_map(|field| {
+            let raw = extraction.fields.get(field.as_str())?;
+            Some((*field, parse_fundamental_value(raw)))
+        })
+        .collect())
+}
+
+pub fn parse_fundamental_value(raw: &str) -> FundamentalValue {
+    let parsed = parse_detail_value(raw);
+    let (value, unit) = match parse_unit(parsed.unit.as_deref().unwrap_or("")) {
+        Some((scale, unit)) => (parsed.value.map(|v| v * scale), unit.map(str::to_string)),
+        None => (None, None),
+    };
+    FundamentalValue { display: parsed.display, value, unit, note: parsed.note }
+}
+
+pub async fn scrape_fundamentals(source: &impl QuoteSource, code: &str) -> Result<FundamentalsData> {
+    let html = source.fetch_html(code).await?;
+    let document = Html::parse_document(&html);
+    let fundamentals = extract_fundamentals(&document)?;
+    if fundamentals.is_empty() {
+        return Err(Error::from(format!("No fundamentals found for {}", code)));
+    }
+    Ok(FundamentalsData { code: code.to_string(), fundamentals })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FixtureSource;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> Option<Decimal> {
+        Some(Decimal::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn units_are_normalized() {
+        let v = parse_fundamental_value("52,123,456百万円(10/17)");
+        assert_eq!((v.value, v.unit.as_deref(), v.note.as_deref()), (dec("52123456000000"), Some("JPY"), Some("10/17")));
+        let v = parse_fundamental_value("12.34倍");
+        assert_eq!((v.value, v.unit.as_deref()), (dec("12.34"), Some("times")));
+        let v = parse_fundamental_value("2.88%");
+        assert_eq!((v.value, v.unit.as_deref()), (dec("2.88"), Some("percent")));
+        let v = parse_fundamental_value("100株");
+        assert_eq!((v.value, v.unit.as_deref()), (dec("100"), Some("shares")));
+        // 知らない単位は倍数がわからないので数値を返さない
+        let v = parse_fundamental_value("1.5ユーロ");
+        assert_eq!((v.value, v.unit), (None, None));
+    }
+
+    #[test]
+    fn japanese_stock_layout() {
+        let document = Html::parse_document(
+            r#"<ul>
+                 <li><dl><dt><span>時価総額</span></dt><dd>47,000,000<span>百万円</span>(15:00)</dd></dl></li>
+                 <li><dl><dt><span>PER</span><span>（会社予想）</span></dt><dd>10.21<span>倍</span>(15:00)</dd></dl></li>
+                 <li><dl><dt><span>配当利回り</span><span>（会社予想）</span></dt><dd>2.58<span>%</span>(15:00)</dd></dl></li>
+                 <li><dl><dt><span>単元株数</span></dt><dd>100<span>株</span></dd></dl></li>
+               </ul>"#,
+        );
+        let fundamentals = extract_fundamentals(&document).unwrap();
+        let value = |f: FundamentalField| fundamentals.get(&f).and_then(|v| v.value);
+        assert_eq!(value(FundamentalField::MarketCap), dec("47000000000000"));
+        assert_eq!(value(FundamentalField::Per), dec("10.21"));
+        assert_eq!(value(FundamentalField::DividendYield), dec("2.58"));
+        assert_eq!(value(FundamentalField::UnitShares), dec("100"));
+        assert!(!fundamentals.contains_key(&FundamentalField::Pbr));
+    }
+
+    #[test]
+    fn us_stock_fixture() {
+        let source = FixtureSource::new().with_page("SONY", include_str!("../sample.html"));
+        let data = futures::executor::block_on(scrape_fundamentals(&source, "SONY")).unwrap();
+        let f = &data.fundamentals;
+        assert_eq!(f[&FundamentalField::MarketCap].value, dec("165532028000"));
+        assert_eq!(f[&FundamentalField::MarketCap].unit.as_deref(), Some("USD"));
+        assert_eq!(f[&FundamentalField::SharesOutstanding].value, dec("5965118130"));
+        // 実績値が出ていない指標は表示だけ返す
+        assert_eq!(f[&FundamentalField::Per].value, None);
+        assert_eq!(f[&FundamentalField::Per].display, "---(--:--)");
+        assert!(f.contains_key(&FundamentalField::Bps));
+    }
+
+    #[test]
+    fn pages_without_fundamentals_are_errors() {
+        let source = FixtureSource::new().with_page("EMPTY", "<html><body><p>no data</p></body></html>");
+        assert!(futures::executor::block_on(scrape_fundamentals(&source, "EMPTY")).is_err());
+    }
+}
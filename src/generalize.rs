@@ -0,0 +1,113 @@
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::api_cors;
+use crate::batch::{run_batch, BatchConfig};
+use crate::selector_generator::{generalize_selectors, GeneralizedSelector, PageSample};
+use crate::source::{self, QuoteSource};
+use crate::text_match::{MatchMode, TextMatcher};
+
+// --- POST /api/generalize-selectors ---
+// 複数の銘柄ページ (URL・コード・HTML のいずれか) と、それぞれのページでの対象テキストを受け取り、
+// 全ページで対象を取れるセレクターを探す。ページ種別ごとに1つのプロファイルを作るためのもの。
+
+const MAX_SAMPLES: usize = 10;
+
+#[derive(Deserialize, Debug)]
+struct GeneralizeRequest {
+    samples: Vec<SampleInput>,
+    // 全サンプル共通の照合方法 (既定は exact)
+    #[serde(default, rename = "match")]
+    mode: MatchMode,
+}
+
+#[derive(Deserialize, Debug)]
+struct SampleInput {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    html: Option<String>,
+    text: String,
+}
+
+impl SampleInput {
+    // 応答でサンプルを識別するための表示
+    fn label(&self, index: usize) -> String {
+        self.url
+            .clone()
+            .or_else(|| self.code.clone())
+            .unwrap_or_else(|| format!("html#{}", index))
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct GeneralizeResponse {
+    // selectors[].match_counts と同じ順序
+    samples: Vec<String>,
+    selectors: Vec<GeneralizedSelector>,
+}
+
+pub async fn preflight(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    Response::empty()?.with_cors(&api_cors())
+}
+
+pub async fn handle_generalize(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: GeneralizeRequest = match req.json().await {
+        Ok(b) => b,
+        Err(e) => return Response::error(format!("Invalid request body: {}", e), 400)?.with_cors(&api_cors()),
+    };
+    if body.samples.len() < 2 {
+        return Response::error("At least 2 samples are required", 400)?.with_cors(&api_cors());
+    }
+    if body.samples.len() > MAX_SAMPLES {
+        return Response::error(format!("At most {} samples are allowed", MAX_SAMPLES), 400)?.with_cors(&api_cors());
+    }
+    if let Some(i) = body.samples.iter().position(|s| s.url.is_none() && s.code.is_none() && s.html.is_none()) {
+        return Response::error(format!("Sample {} needs one of 'url', 'code' or 'html'", i), 400)?.with_cors(&api_cors());
+    }
+
+    let mut matchers = Vec::new();
+    for (i, sample) in body.samples.iter().enumerate() {
+        match TextMatcher::new(body.mode, &sample.text) {
+            Ok(m) => matchers.push(m),
+            Err(e) => return Response::error(format!("Sample {}: {}", i, e), 400)?.with_cors(&api_cors()),
+        }
+    }
+
+    // HTML が直接渡されていないサンプルだけ取得する
+    let config = BatchConfig::from_ctx(&ctx);
+    let pages = run_batch(body.samples.iter().collect(), &config, |sample| async move {
+        if let Some(html) = &sample.html {
+            return Ok(html.clone());
+        }
+        let url = match (&sample.url, &sample.code) {
+            (Some(url), _) => url.clone(),
+            (None, Some(code)) => source::default_source().quote_url(code),
+            (None, None) => return Err(Error::from("Sample has no source")),
+        };
+        source::fetch_text(&url).await
+    })
+    .await;
+
+    // 1ページでも取得できなければ比較にならないので、どのサンプルかを返して終える
+    let mut documents = Vec::new();
+    for (i, (sample, page)) in body.samples.iter().zip(pages).enumerate() {
+        match page {
+            Ok(html) => documents.push(Html::parse_document(&html)),
+            Err(e) => return Response::error(format!("Failed to fetch sample {} ({}): {}", i, sample.label(i), e), 502)?.with_cors(&api_cors()),
+        }
+    }
+
+    let samples: Vec<PageSample> = documents
+        .iter()
+        .zip(&matchers)
+        .map(|(document, target)| PageSample { document, target })
+        .collect();
+    let selectors = generalize_selectors(&samples);
+    let labels = body.samples.iter().enumerate().map(|(i, s)| s.label(i)).collect();
+
+    Response::from_json(&GeneralizeResponse { samples: labels, selectors })?.with_cors(&api_cors())
+}
@@ -0,0 +1,226 @@
+use scraper::{Element, ElementRef, Html, Selector};
+use serde::Serialize;
+
+// --- ラベルを手がかりにした値の抽出 ---
+// 詳細欄の「前日終値」「始値」「高値」のような項目は、クラス名は変わってもラベルの文言はまず変わらない。
+// ラベルの要素を見つけ、dt/dd・th/td・隣の要素・共通の親の順に対応する値の要素をたどる。
+
+// ラベルとみなすテキストの長さの上限 (文字数)
+const MAX_LABEL_CHARS: usize = 20;
+// 共通の親として遡る階層数
+const MAX_CONTAINER_LEVELS: usize = 3;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelRelation {
+    // <dt>ラベル</dt><dd>値</dd>
+    DefinitionList,
+    // <th>ラベル</th><td>値</td> (ラベルが td の場合も同じ行の次のセル)
+    TableRow,
+    // ラベル (を包む要素) の次の兄弟要素
+    Sibling,
+    // ラベルを含む祖先の次の兄弟要素
+    Container,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LabeledValue<'a> {
+    pub label: ElementRef<'a>,
+    pub value: ElementRef<'a>,
+    pub relation: LabelRelation,
+}
+
+impl LabeledValue<'_> {
+    // 値の要素のテキスト (空白は1つにまとめる)
+    pub fn text(&self) -> String {
+        collapse(&self.value.text().collect::<String>())
+    }
+}
+
+// ラベルで値を特定するルール。生成したルールは FieldRule.labels にそのまま使える
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LabelRule {
+    pub label: String,
+    pub relation: LabelRelation,
+    // 例として渡されたページで取れた値
+    pub value: String,
+}
+
+/// label と同じテキストを持つ最初の要素を探し、対応する値の要素を返す
+pub fn find_labeled_value<'a>(document: &'a Html, label: &str) -> Option<LabeledValue<'a>> {
+    let wanted = strip_whitespace(label);
+    if wanted.is_empty() {
+        return None;
+    }
+    find_label_elements(document, &wanted).into_iter().find_map(resolve_value)
+}
+
+// テキストが label と一致する要素のうち最も内側のもの (文書順)。完全一致がなければ前方一致
+fn find_label_elements<'a>(document: &'a Html, wanted: &str) -> Vec<ElementRef<'a>> {
+    let Ok(all) = Selector::parse("*") else {
+        return Vec::new();
+    };
+    let innermost = |matches: &dyn Fn(&str) -> bool| -> Vec<ElementRef<'a>> {
+        document
+            .select(&all)
+            .filter(|el| matches(&element_text(*el)))
+            .filter(|el| !el.child_elements().any(|child| matches(&element_text(child))))
+            .collect()
+    };
+    let exact = innermost(&|text| text == wanted);
+    if !exact.is_empty() {
+        return exact;
+    }
+    // "始値 ?" のように補足アイコンの文字が付いている場合
+    innermost(&|text| text.starts_with(wanted) && text.chars().count() <= MAX_LABEL_CHARS)
+}
+
+fn resolve_value(label: ElementRef) -> Option<LabeledValue> {
+    let found = |value, relation| Some(LabeledValue { label, value, relation });
+
+    // 1. dt/dd、th/td
+    for el in std::iter::once(label).chain(label.ancestors().filter_map(ElementRef::wrap).take(MAX_CONTAINER_LEVELS)) {
+        match el.value().name() {
+            "dt" => {
+                if let Some(dd) = next_siblings(el).find(|s| s.value().name() == "dd") {
+                    return found(dd, LabelRelation::DefinitionList);
+                }
+            }
+            "th" | "td" => {
+                if let Some(td) = next_siblings(el).find(|s| s.value().name() == "td" && has_text(*s)) {
+                    return found(td, LabelRelation::TableRow);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 2. ラベルだけを包む要素まで遡り、その次の兄弟
+    let label_text = element_text(label);
+    let mut wrapper = label;
+    while let Some(parent) = wrapper.parent_element() {
+        if element_text(parent) != label_text {
+            break;
+        }
+        wrapper = parent;
+    }
+    if let Some(value) = next_siblings(wrapper).find(|s| has_text(*s)) {
+        return found(value, LabelRelation::Sibling);
+    }
+
+    // 3. さらに上の祖先の次の兄弟
+    wrapper
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .take(MAX_CONTAINER_LEVELS)
+        .find_map(|ancestor| next_siblings(ancestor).find(|s| has_text(*s)))
+        .and_then(|value| found(value, LabelRelation::Container))
+}
+
+/// value (またはそれを含む要素) を指すラベルを target の周辺から探し、実際にそのラベルから value に戻れるものを返す
+pub fn label_rules_for(document: &Html, target: ElementRef) -> Vec<LabelRule> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut push = |el: ElementRef| {
+        let text = collapse(&el.text().collect::<Vec<_>>().join(" "));
+        let plausible = !text.is_empty()
+            && text.chars().count() <= MAX_LABEL_CHARS
+            && text.chars().any(|c| c.is_alphabetic())
+            && !labels.contains(&text);
+        if plausible {
+            labels.push(text);
+        }
+    };
+    // target と祖先の前の兄弟 (dt・th・ラベルの span など)
+    for el in std::iter::once(target).chain(target.ancestors().filter_map(ElementRef::wrap).take(MAX_CONTAINER_LEVELS)) {
+        if let Some(prev) = el.prev_siblings().filter_map(ElementRef::wrap).find(|s| has_text(*s)) {
+            // ラベルは末端の要素に限る (<dt><span>始値</span><i>?</i></dt> なら span)。
+            // 前の項目全体 ("高値 2,880") をラベルとみなさないため
+            for leaf in prev.descendants().filter_map(ElementRef::wrap).filter(|d| d.child_elements().next().is_none()) {
+                push(leaf);
+            }
+        }
+    }
+
+    labels
+        .into_iter()
+        .filter_map(|label| {
+            let resolved = find_labeled_value(document, &label)?;
+            let points_at_target =
+                resolved.value.id() == target.id() || target.ancestors().any(|a| a.id() == resolved.value.id());
+            points_at_target.then(|| LabelRule { value: resolved.text(), relation: resolved.relation, label })
+        })
+        .collect()
+}
+
+fn next_siblings(el: ElementRef) -> impl Iterator<Item = ElementRef> {
+    el.next_siblings().filter_map(ElementRef::wrap)
+}
+
+fn has_text(el: ElementRef) -> bool {
+    el.text().any(|t| !t.trim().is_empty())
+}
+
+fn element_text(el: ElementRef) -> String {
+    strip_whitespace(&el.text().collect::<String>())
+}
+
+fn strip_whitespace(text: &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_of(html: &str, label: &str) -> Option<(String, LabelRelation)> {
+        let document = Html::parse_document(html);
+        find_labeled_value(&document, label).map(|v| (v.text(), v.relation))
+    }
+
+    #[test]
+    fn definition_lists() {
+        let html = r#"<dl><dt class="_DataListItem__title_a1b2"><span>前日終値</span><i>?</i></dt>
+                      <dd><span class="_StyledNumber__value_9o0uf_9">2,862.5</span></dd></dl>"#;
+        assert_eq!(value_of(html, "前日終値"), Some(("2,862.5".into(), LabelRelation::DefinitionList)));
+    }
+
+    #[test]
+    fn table_rows() {
+        let html = "<table><tr><th>高値</th><td>2,880</td></tr><tr><th>安値</th><td>2,840</td></tr></table>";
+        assert_eq!(value_of(html, "安値"), Some(("2,840".into(), LabelRelation::TableRow)));
+    }
+
+    #[test]
+    fn siblings_and_wrappers() {
+        let html = r#"<div><div><span>始値</span></div><div><span>2,850</span></div></div>"#;
+        assert_eq!(value_of(html, "始値"), Some(("2,850".into(), LabelRelation::Sibling)));
+    }
+
+    #[test]
+    fn shared_containers() {
+        // ラベルの前に注記が付いていて、ラベルだけを包む要素がない
+        let html = r#"<div class="item"><p><em>※</em><span>出来高</span></p></div><div>1,234,500 株</div>"#;
+        assert_eq!(value_of(html, "出来高"), Some(("1,234,500 株".into(), LabelRelation::Container)));
+    }
+
+    #[test]
+    fn missing_labels() {
+        assert_eq!(value_of("<p>始値</p>", "始値"), None);
+        assert_eq!(value_of("<dl><dt>始値</dt><dd>1</dd></dl>", "終値"), None);
+    }
+
+    #[test]
+    fn rules_are_generated_from_the_value() {
+        let document = Html::parse_document(
+            r#"<dl><dt><span>高値</span></dt><dd><span class="v">2,880</span></dd></dl>
+               <dl><dt><span>安値</span></dt><dd><span class="v">2,840</span></dd></dl>"#,
+        );
+        let target = document.select(&Selector::parse("dl:nth-of-type(2) span.v").unwrap()).next().unwrap();
+        let rules = label_rules_for(&document, target);
+        assert_eq!(rules, vec![LabelRule { label: "安値".into(), relation: LabelRelation::DefinitionList, value: "2,840".into() }]);
+    }
+}
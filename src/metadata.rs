@@ -0,0 +1,217 @@
+use scraper::{Html, Selector};
+use serde::Serialize;
+use serde_json::Value;
+
+// --- ページのメタデータ (JSON-LD・OpenGraph・Twitter カード・canonical・description) ---
+// <title> を "【" や "：" で切るよりも、og:title やパンくずリストの JSON-LD の方が銘柄名を素直に持っている。
+// 探索 (discover_data) の名前候補と /metadata?url=... の両方で使う。
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct OpenGraph {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TwitterCard {
+    pub card: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub canonical_url: Option<String>,
+    pub keywords: Vec<String>,
+    pub open_graph: OpenGraph,
+    pub twitter: TwitterCard,
+    // <script type="application/ld+json"> の各項目 (配列や @graph は展開済み)
+    pub json_ld: Vec<Value>,
+}
+
+impl PageMetadata {
+    pub fn from_document(document: &Html) -> Self {
+        let mut meta = PageMetadata { title: first_text(document, "title"), ..Default::default() };
+
+        if let Ok(sel) = Selector::parse("link[rel~='canonical'][href]") {
+            meta.canonical_url = document.select(&sel).find_map(|el| non_empty(el.value().attr("href")));
+        }
+
+        if let Ok(sel) = Selector::parse("meta[content]") {
+            for el in document.select(&sel) {
+                let attrs = el.value();
+                let Some(key) = attrs.attr("property").or_else(|| attrs.attr("name")) else {
+                    continue;
+                };
+                let Some(content) = non_empty(attrs.attr("content")) else {
+                    continue;
+                };
+                // 同じ項目が複数あれば最初のものを使う
+                let slot = match key.trim().to_ascii_lowercase().as_str() {
+                    "description" => &mut meta.description,
+                    "og:title" => &mut meta.open_graph.title,
+                    "og:description" => &mut meta.open_graph.description,
+                    "og:url" => &mut meta.open_graph.url,
+                    "og:image" => &mut meta.open_graph.image,
+                    "og:site_name" => &mut meta.open_graph.site_name,
+                    "og:type" => &mut meta.open_graph.kind,
+                    "twitter:card" => &mut meta.twitter.card,
+                    "twitter:title" => &mut meta.twitter.title,
+                    "twitter:description" => &mut meta.twitter.description,
+                    "twitter:image" => &mut meta.twitter.image,
+                    "twitter:site" => &mut meta.twitter.site,
+                    "keywords" if meta.keywords.is_empty() => {
+                        meta.keywords = content.split([',', '、']).map(str::trim).filter(|k| !k.is_empty()).map(str::to_string).collect();
+                        continue;
+                    }
+                    _ => continue,
+                };
+                slot.get_or_insert(content);
+            }
+        }
+
+        if let Ok(sel) = Selector::parse("script[type='application/ld+json']") {
+            for el in document.select(&sel) {
+                // 壊れた JSON-LD は読み飛ばす
+                if let Ok(value) = serde_json::from_str::<Value>(&el.text().collect::<String>()) {
+                    flatten_json_ld(value, &mut meta.json_ld);
+                }
+            }
+        }
+        meta
+    }
+
+    /// canonical → og:url の順
+    pub fn url(&self) -> Option<&str> {
+        self.canonical_url.as_deref().or(self.open_graph.url.as_deref())
+    }
+
+    /// @type が ty の JSON-LD 項目 (@type が配列の場合も含む)
+    pub fn json_ld_of_type<'a>(&'a self, ty: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+        self.json_ld.iter().filter(move |item| match &item["@type"] {
+            Value::String(t) => t == ty,
+            Value::Array(types) => types.iter().any(|t| t.as_str() == Some(ty)),
+            _ => false,
+        })
+    }
+
+    /// 銘柄名の候補を確からしい順に返す (名前と取り出し元)。
+    /// パンくずリストの最後 (現在のページ) → og:title → twitter:title → <title>
+    pub fn name_candidates(&self) -> Vec<(String, &'static str)> {
+        let breadcrumb = self.json_ld_of_type("BreadcrumbList").find_map(|list| {
+            list["itemListElement"]
+                .as_array()?
+                .iter()
+                .max_by_key(|item| item["position"].as_u64().unwrap_or(0))?["name"]
+                .as_str()
+                .map(str::to_string)
+        });
+        let sources = [
+            (breadcrumb, "JSON-LD BreadcrumbList"),
+            (self.open_graph.title.clone(), "og:title"),
+            (self.twitter.title.clone(), "twitter:title"),
+            (self.title.clone(), "<title>"),
+        ];
+        let mut names: Vec<(String, &'static str)> = Vec::new();
+        for (raw, source) in sources {
+            let Some(name) = raw.map(|r| clean_title(&r)).filter(|n| !n.is_empty()) else {
+                continue;
+            };
+            if !names.iter().any(|(n, _)| *n == name) {
+                names.push((name, source));
+            }
+        }
+        names
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name_candidates().into_iter().next().map(|(name, _)| name)
+    }
+}
+
+/// "ソニーグループ(株)【SONY】：株価・株式情報 - Yahoo!ファイナンス" → "ソニーグループ(株)"。
+/// "(株)" を残すため "(" では切らない
+pub fn clean_title(title: &str) -> String {
+    title
+        .split(" - ")
+        .next()
+        .unwrap_or("")
+        .split('【')
+        .next()
+        .unwrap_or("")
+        .split('：')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_string()
+}
+
+fn flatten_json_ld(value: Value, out: &mut Vec<Value>) {
+    match value {
+        Value::Array(items) => items.into_iter().for_each(|item| flatten_json_ld(item, out)),
+        Value::Object(mut map) => match map.remove("@graph") {
+            Some(graph) => flatten_json_ld(graph, out),
+            None => out.push(Value::Object(map)),
+        },
+        _ => {}
+    }
+}
+
+fn first_text(document: &Html, selector: &str) -> Option<String> {
+    let sel = Selector::parse(selector).ok()?;
+    document.select(&sel).find_map(|el| non_empty(Some(&el.text().collect::<String>())))
+}
+
+fn non_empty(text: Option<&str>) -> Option<String> {
+    text.map(str::trim).filter(|t| !t.is_empty()).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn us_stock_fixture() {
+        let meta = PageMetadata::from_document(&Html::parse_document(include_str!("../sample.html")));
+        assert_eq!(meta.url(), Some("https://finance.yahoo.co.jp/quote/SONY"));
+        assert_eq!(meta.open_graph.kind.as_deref(), Some("website"));
+        assert!(meta.description.as_deref().is_some_and(|d| d.starts_with("ソニーグループ(株)【SONY】")));
+        assert!(meta.keywords.contains(&"米国株".to_string()));
+        assert_eq!(meta.json_ld_of_type("BreadcrumbList").count(), 1);
+        assert_eq!(meta.name().as_deref(), Some("ソニーグループ(株)"));
+        assert_eq!(meta.name_candidates()[0].1, "JSON-LD BreadcrumbList");
+    }
+
+    #[test]
+    fn index_fixture() {
+        let meta = PageMetadata::from_document(&Html::parse_document(include_str!("../DJI.html")));
+        assert_eq!(meta.canonical_url.as_deref(), Some("https://finance.yahoo.co.jp/quote/^DJI"));
+        assert_eq!(meta.twitter.card.as_deref(), Some("summary_large_image"));
+        assert_eq!(meta.name().as_deref(), Some("NYダウ"));
+    }
+
+    #[test]
+    fn json_ld_graphs_and_broken_blocks() {
+        let meta = PageMetadata::from_document(&Html::parse_document(
+            r#"<head>
+                 <script type="application/ld+json">{"@graph":[{"@type":"Corporation","name":"トヨタ自動車"},{"@type":["WebPage","ItemPage"]}]}</script>
+                 <script type="application/ld+json">{ broken</script>
+                 <meta property="og:title" content="トヨタ自動車(株)【7203】：株価 - Yahoo!ファイナンス">
+                 <meta property="og:title" content="second">
+               </head>"#,
+        ));
+        assert_eq!(meta.json_ld.len(), 2);
+        assert_eq!(meta.json_ld_of_type("ItemPage").count(), 1);
+        assert_eq!(meta.open_graph.title.as_deref(), Some("トヨタ自動車(株)【7203】：株価 - Yahoo!ファイナンス"));
+        assert_eq!(meta.name_candidates(), vec![("トヨタ自動車(株)".to_string(), "og:title")]);
+    }
+}
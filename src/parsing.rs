@@ -0,0 +1,247 @@
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// --- 表示用文字列 → 数値 の正規化 ---
+// Yahoo の表示は "1,234.5" "+12.3(+1.02%)" のような文字列で、全角数字や "---" も混ざる。
+// ここで一度だけ数値に直し、クライアントが再パースしなくて済むようにする。
+
+// 価格・前日比を数値化したもの。パースできなかった項目は None
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QuoteValues {
+    pub price: Option<Decimal>,
+    pub change_abs: Option<Decimal>,
+    // パーセント値 (1.02% => 1.02)
+    pub change_pct: Option<Decimal>,
+}
+
+impl QuoteValues {
+    pub fn from_display(price: &str, change_abs: &str, change_pct: &str) -> Self {
+        // change_abs に "+12.3(+1.02%)" がまるごと入っている場合にも対応する
+        let combined = parse_change(change_abs);
+        QuoteValues {
+            price: parse_decimal(price),
+            change_abs: parse_decimal(change_abs).or(combined.abs),
+            change_pct: parse_percent(change_pct).or(combined.pct),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedChange {
+    pub abs: Option<Decimal>,
+    pub pct: Option<Decimal>,
+}
+
+// --- 前日比解析 ---
+pub fn parse_change_string(combined: &str) -> (String, String) {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"([\-+]?[\d,]+(?:\.\d+)?).*?\((.*%?.*)\)").unwrap());
+    if let Some(caps) = re.captures(combined) {
+        let abs = caps.get(1).map_or("", |m| m.as_str()).trim().to_string();
+        let pct = caps.get(2).map_or("", |m| m.as_str()).trim().to_string();
+        (abs, pct)
+    } else {
+        (combined.trim().to_string(), "".to_string())
+    }
+}
+
+/// "+12.3(+1.02%)" 形式を符号付きの数値に分解する (全角表記にも対応)
+pub fn parse_change(combined: &str) -> ParsedChange {
+    let normalized = normalize_numeric_text(combined);
+    let (abs, pct) = parse_change_string(&normalized);
+    ParsedChange { abs: parse_decimal(&abs), pct: parse_percent(&pct) }
+}
+
+/// 全角英数記号・全角スペースを半角にそろえる
+pub fn to_half_width(text: &str) -> String {
+    text.chars().map(half_width_char).collect()
+}
+
+fn half_width_char(c: char) -> char {
+    match c {
+        // 全角 ASCII (！〜～) は 0xFEE0 引くと半角になる
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
+
+/// 全角英数記号を半角に、各種マイナス記号を '-' にそろえ、カンマと空白を取り除く
+pub fn normalize_numeric_text(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| {
+            let c = match half_width_char(c) {
+                '\u{2212}' | '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{FE63}' => '-',
+                '\u{FE62}' => '+',
+                c => c,
+            };
+            if c == ',' || c.is_whitespace() {
+                None
+            } else {
+                Some(c)
+            }
+        })
+        .collect()
+}
+
+/// 表示用の数値文字列を Decimal にする。"---" のようなプレースホルダーや数値以外は None
+pub fn parse_decimal(text: &str) -> Option<Decimal> {
+    let normalized = normalize_numeric_text(text);
+    let digits = normalized.strip_prefix(['+', '-']).unwrap_or(&normalized);
+    let is_numeric = !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.chars().filter(|&c| c == '.').count() <= 1
+        && digits.chars().any(|c| c.is_ascii_digit());
+    if !is_numeric {
+        return None;
+    }
+    let value = Decimal::from_str(digits).ok()?;
+    Some(if normalized.starts_with('-') { -value } else { value })
+}
+
+/// "+1.02%" "(+1.02%)" "1.02" をパーセント値 1.02 にする
+pub fn parse_percent(text: &str) -> Option<Decimal> {
+    let normalized = normalize_numeric_text(text);
+    let inner = normalized.trim_start_matches('(').trim_end_matches(')');
+    let value = inner.strip_suffix('%').unwrap_or(inner);
+    parse_decimal(value)
+}
+
+// 単位の前に付く倍数。"百万" を "万" より先に照合する
+const UNIT_SCALES: [(&str, i64); 5] =
+    [("兆", 1_000_000_000_000), ("百万", 1_000_000), ("億", 100_000_000), ("万", 10_000), ("千", 1_000)];
+// 倍数を除いた単位とその正規化名
+const BASE_UNITS: [(&str, &str); 5] = [("円", "JPY"), ("ドル", "USD"), ("株", "shares"), ("倍", "times"), ("%", "percent")];
+
+/// "百万円" "千ドル" "倍" "%" のような単位を、倍数と正規化した単位名 ("JPY" など) に分ける。
+/// "千" だけなら単位名は None。知らない単位なら None を返す
+pub fn parse_unit(unit: &str) -> Option<(Decimal, Option<&'static str>)> {
+    let unit = to_half_width(unit.trim());
+    let (scale, rest) = UNIT_SCALES
+        .iter()
+        .find_map(|(prefix, scale)| unit.strip_prefix(prefix).map(|rest| (Decimal::from(*scale), rest)))
+        .unwrap_or((Decimal::ONE, unit.as_str()));
+    if rest.is_empty() {
+        return Some((scale, None));
+    }
+    let name = BASE_UNITS.iter().find(|(suffix, _)| *suffix == rest)?.1;
+    Some((scale, Some(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parse_decimal_handles_commas_and_signs() {
+        assert_eq!(parse_decimal("1,234.5"), Some(dec("1234.5")));
+        assert_eq!(parse_decimal("38,215"), Some(dec("38215")));
+        assert_eq!(parse_decimal("+12.3"), Some(dec("12.3")));
+        assert_eq!(parse_decimal("-0.75"), Some(dec("-0.75")));
+        assert_eq!(parse_decimal("  2,862.5 "), Some(dec("2862.5")));
+    }
+
+    #[test]
+    fn parse_decimal_is_exact() {
+        // f64 では 0.1 + 0.2 のような誤差が出るが、Decimal は表示通りの値を保つ
+        assert_eq!(parse_decimal("0.1").unwrap() + parse_decimal("0.2").unwrap(), dec("0.3"));
+        assert_eq!(parse_decimal("149.870").unwrap().to_string(), "149.870");
+    }
+
+    #[test]
+    fn parse_decimal_handles_full_width_and_unicode_minus() {
+        assert_eq!(parse_decimal("１，２３４．５"), Some(dec("1234.5")));
+        assert_eq!(parse_decimal("＋１２"), Some(dec("12")));
+        assert_eq!(parse_decimal("－３．５"), Some(dec("-3.5")));
+        assert_eq!(parse_decimal("\u{2212}3.5"), Some(dec("-3.5")));
+        assert_eq!(parse_decimal("1\u{3000}234"), Some(dec("1234")));
+    }
+
+    #[test]
+    fn parse_decimal_rejects_placeholders_and_non_numbers() {
+        assert_eq!(parse_decimal("---"), None);
+        assert_eq!(parse_decimal("-"), None);
+        assert_eq!(parse_decimal(""), None);
+        assert_eq!(parse_decimal("N/A"), None);
+        assert_eq!(parse_decimal("7203.T"), None);
+        assert_eq!(parse_decimal("1.2.3"), None);
+        assert_eq!(parse_decimal("15:00"), None);
+        assert_eq!(parse_decimal("+-1"), None);
+    }
+
+    #[test]
+    fn parse_percent_accepts_parentheses_and_percent_sign() {
+        assert_eq!(parse_percent("+1.02%"), Some(dec("1.02")));
+        assert_eq!(parse_percent("(-0.35%)"), Some(dec("-0.35")));
+        assert_eq!(parse_percent("（＋１．０２％）"), Some(dec("1.02")));
+        assert_eq!(parse_percent("0.5"), Some(dec("0.5")));
+        assert_eq!(parse_percent("---%"), None);
+        assert_eq!(parse_percent(""), None);
+    }
+
+    #[test]
+    fn parse_change_string_splits_abs_and_pct() {
+        assert_eq!(parse_change_string("+12.3(+1.02%)"), ("+12.3".to_string(), "+1.02%".to_string()));
+        assert_eq!(parse_change_string("-1,234 (-0.50%)"), ("-1,234".to_string(), "-0.50%".to_string()));
+        assert_eq!(parse_change_string(" +5 "), ("+5".to_string(), "".to_string()));
+    }
+
+    #[test]
+    fn parse_change_returns_signed_values() {
+        assert_eq!(parse_change("+12.3(+1.02%)"), ParsedChange { abs: Some(dec("12.3")), pct: Some(dec("1.02")) });
+        assert_eq!(parse_change("-1,234.5(-0.50%)"), ParsedChange { abs: Some(dec("-1234.5")), pct: Some(dec("-0.50")) });
+        assert_eq!(parse_change("前日比+12.3(+1.02%)"), ParsedChange { abs: Some(dec("12.3")), pct: Some(dec("1.02")) });
+        assert_eq!(parse_change("－１２．３（－１．０２％）"), ParsedChange { abs: Some(dec("-12.3")), pct: Some(dec("-1.02")) });
+    }
+
+    #[test]
+    fn parse_change_handles_placeholders() {
+        assert_eq!(parse_change("---(---%)"), ParsedChange::default());
+        assert_eq!(parse_change(""), ParsedChange::default());
+        assert_eq!(parse_change("+0(0.00%)"), ParsedChange { abs: Some(dec("0")), pct: Some(dec("0.00")) });
+    }
+
+    #[test]
+    fn quote_values_from_display_strings() {
+        let values = QuoteValues::from_display("2,862.5", "+12.5", "(+0.44%)");
+        assert_eq!(values.price, Some(dec("2862.5")));
+        assert_eq!(values.change_abs, Some(dec("12.5")));
+        assert_eq!(values.change_pct, Some(dec("0.44")));
+    }
+
+    #[test]
+    fn quote_values_falls_back_to_combined_change() {
+        let values = QuoteValues::from_display("---", "-3.5(-0.12%)", "");
+        assert_eq!(values.price, None);
+        assert_eq!(values.change_abs, Some(dec("-3.5")));
+        assert_eq!(values.change_pct, Some(dec("-0.12")));
+    }
+
+    #[test]
+    fn parse_unit_splits_scale_and_unit() {
+        assert_eq!(parse_unit("百万円"), Some((dec("1000000"), Some("JPY"))));
+        assert_eq!(parse_unit("千ドル"), Some((dec("1000"), Some("USD"))));
+        assert_eq!(parse_unit("億円"), Some((dec("100000000"), Some("JPY"))));
+        assert_eq!(parse_unit("倍"), Some((dec("1"), Some("times"))));
+        assert_eq!(parse_unit("％"), Some((dec("1"), Some("percent"))));
+        assert_eq!(parse_unit("千"), Some((dec("1000"), None)));
+        assert_eq!(parse_unit(""), Some((dec("1"), None)));
+        assert_eq!(parse_unit("ユーロ"), None);
+    }
+
+    #[test]
+    fn quote_values_serialize_as_numbers() {
+        let values = QuoteValues::from_display("1,234.5", "+1", "");
+        let json = serde_json::to_value(&values).unwrap();
+        assert_eq!(json["price"], serde_json::json!(1234.5));
+        assert_eq!(json["change_abs"], serde_json::json!(1.0));
+        assert!(json["change_pct"].is_null());
+    }
+}
@@ -0,0 +1,316 @@
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use worker::*;
+
+use crate::label::find_labeled_value;
+use crate::script_state::ScriptStates;
+use crate::parsing::parse_change_string;
+use crate::{find_with_fallback_matched, PageType, StockData};
+
+// --- 宣言的な抽出プロファイル ---
+// コンテナ候補・フィールドごとのセレクター候補・後処理をデータとして記述し、
+// extract() がそれを実行する。新しいページレイアウトは JSON を追加するだけで対応できる。
+
+const STOCK_PROFILE: &str = include_str!("../profiles/stock.json");
+const PRICEBOARD_PROFILE: &str = include_str!("../profiles/priceboard.json");
+const INDEX_PROFILE: &str = include_str!("../profiles/index.json");
+// 詳細欄 (始値・高値・出来高など)。ページ種別によらずラベルで探す
+const DETAIL_PROFILE: &str = include_str!("../profiles/detail.json");
+// 指標欄 (時価総額・PER・PBR・配当利回りなど)。詳細欄と同じくラベルで探す
+const FUNDAMENTALS_PROFILE: &str = include_str!("../profiles/fundamentals.json");
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtractionProfile {
+    pub name: String,
+    // 上から順に試すコンテナ。空ならページ全体を対象にする
+    #[serde(default)]
+    pub containers: Vec<String>,
+    pub fields: Vec<FieldRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldRule {
+    pub field: String,
+    // 上から順に試すセレクター (find_with_fallback と同じ)
+    #[serde(default)]
+    pub selectors: Vec<String>,
+    // セレクターで見つからなければ、埋め込み JSON のパス ("__PRELOADED_STATE__.pageInfo.title" など) を上から順に試す
+    #[serde(default)]
+    pub json: Vec<String>,
+    // それでも見つからなければ、このラベル (「始値」など) に対応する値を上から順に探す
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+    // true なら見つからなかった時点で抽出全体をエラーにする
+    #[serde(default)]
+    pub required: bool,
+    // 見つからなかった場合の値
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+// 抽出したテキストへの後処理。定義順に適用し、最後に必ず trim する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transform {
+    Replace { from: String, to: String },
+    // marker より前 (marker が無ければそのまま)
+    Before { marker: String },
+    // marker より後 (marker が無ければ空文字)
+    After { marker: String },
+    // start と end の間 (どちらかが無ければそのまま)
+    Between { start: String, end: String },
+    // "+12.3(+1.02%)" 形式を parse_change_string で分解した前半/後半
+    ChangeAbs,
+    ChangePct,
+}
+
+impl Transform {
+    pub fn apply(&self, text: &str) -> String {
+        let out = match self {
+            Transform::Replace { from, to } => text.replace(from.as_str(), to),
+            Transform::Before { marker } => match text.find(marker.as_str()) {
+                Some(i) => text[..i].to_string(),
+                None => text.to_string(),
+            },
+            Transform::After { marker } => match text.find(marker.as_str()) {
+                Some(i) => text[i + marker.len()..].to_string(),
+                None => String::new(),
+            },
+            Transform::Between { start, end } => match (text.find(start.as_str()), text.find(end.as_str())) {
+                (Some(s), Some(e)) if s + start.len() <= e => text[s + start.len()..e].to_string(),
+                _ => text.to_string(),
+            },
+            Transform::ChangeAbs => parse_change_string(text).0,
+            Transform::ChangePct => parse_change_string(text).1,
+        };
+        out.trim().to_string()
+    }
+}
+
+// フィールド単位の抽出経過 (/api/test-parser で返す)
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldDiagnostic {
+    pub field: String,
+    // "found" | "default" | "missing" | "invalid_selector" (/api/test-parser の selectors 指定時のみ)
+    pub status: &'static str,
+    // 実際にヒットしたセレクター
+    pub selector: Option<String>,
+    // 後処理前のテキスト
+    pub raw_text: Option<String>,
+    pub value: Option<String>,
+}
+
+// 抽出結果。フィールドが見つからなかった場合も default を入れて返す
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Extraction {
+    pub fields: BTreeMap<String, String>,
+    pub diagnostics: Vec<FieldDiagnostic>,
+}
+
+impl Extraction {
+    pub fn get(&self, field: &str) -> String {
+        self.fields.get(field).cloned().unwrap_or_default()
+    }
+
+    pub fn into_stock_data(self, code: &str) -> StockData {
+        // プロファイルがページからコードを取れない場合はリクエストされたコードを使う
+        let page_code = self.fields.get("code").cloned().unwrap_or_else(|| code.to_string());
+        StockData::new(
+            self.get("name"),
+            page_code,
+            self.get("price"),
+            self.get("change_abs"),
+            self.get("change_pct"),
+            self.get("update_time"),
+        )
+    }
+}
+
+impl ExtractionProfile {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::from(format!("Invalid extraction profile: {}", e)))
+    }
+
+    pub fn builtin(page_type: PageType) -> Result<Self> {
+        Self::from_json(match page_type {
+            PageType::Stock => STOCK_PROFILE,
+            PageType::PriceBoard => PRICEBOARD_PROFILE,
+            PageType::Index => INDEX_PROFILE,
+        })
+    }
+
+    pub fn detail() -> Result<Self> {
+        Self::from_json(DETAIL_PROFILE)
+    }
+
+    pub fn fundamentals() -> Result<Self> {
+        Self::from_json(FUNDAMENTALS_PROFILE)
+    }
+}
+
+/// プロファイルに従ってドキュメントから各フィールドを抽出する汎用エクストラクター
+pub fn extract(document: &Html, profile: &ExtractionProfile) -> Result<Extraction> {
+    // 1️⃣ メインコンテナ探索。見つかったらその内部だけを部分解析する
+    let fragment;
+    let scope = if profile.containers.is_empty() {
+        document
+    } else {
+        let container_el = profile
+            .containers
+            .iter()
+            .filter_map(|sel_str| Selector::parse(sel_str).ok())
+            .find_map(|sel| document.select(&sel).next())
+            .ok_or_else(|| Error::from(format!("Container not found for profile '{}'", profile.name)))?;
+        fragment = Html::parse_fragment(&container_el.html());
+        &fragment
+    };
+
+    // 埋め込み JSON はコンテナの外の <script> にあるので、ページ全体から読む (JSON パスを使うときだけ)
+    let states = if profile.fields.iter().any(|rule| !rule.json.is_empty()) {
+        ScriptStates::from_document(document)
+    } else {
+        ScriptStates::default()
+    };
+
+    // 2️⃣ 各項目を自己修復付きで抽出 (セレクター → JSON パス → ラベル)
+    let mut extraction = Extraction::default();
+    for rule in &profile.fields {
+        let selectors: Vec<&str> = rule.selectors.iter().map(|s| s.as_str()).collect();
+        let matched = find_with_fallback_matched(scope, &selectors)
+            .or_else(|| rule.json.iter().find_map(|path| states.text(path).map(|text| (format!("json:{}", path), text))))
+            .or_else(|| {
+                rule.labels
+                    .iter()
+                    .find_map(|label| find_labeled_value(scope, label).map(|v| (format!("label:{}", label), v.text())))
+            });
+        let (selector, raw_text) = match matched {
+            Some((selector, raw)) => (Some(selector), Some(raw)),
+            None => (None, None),
+        };
+        let found = raw_text.as_ref().map(|raw| rule.transforms.iter().fold(raw.clone(), |text, t| t.apply(&text)));
+        let (status, value) = match (found, &rule.default) {
+            (Some(v), _) => ("found", Some(v)),
+            (None, _) if rule.required => {
+                return Err(Error::from(format!("Required field '{}' not found (profile '{}')", rule.field, profile.name)));
+            }
+            (None, Some(default)) => ("default", Some(default.clone())),
+            (None, None) => ("missing", None),
+        };
+        if let Some(v) = &value {
+            extraction.fields.insert(rule.field.clone(), v.clone());
+        }
+        extraction.diagnostics.push(FieldDiagnostic { field: rule.field.clone(), status, selector, raw_text, value });
+    }
+    Ok(extraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(json: &str) -> Transform {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn replace_transform() {
+        let t = op(r#"{"op":"replace","from":"の指数情報・推移","to":""}"#);
+        assert_eq!(t.apply("NYダウの指数情報・推移 "), "NYダウ");
+        assert_eq!(t.apply("NYダウ"), "NYダウ");
+    }
+
+    #[test]
+    fn before_and_after_transforms() {
+        let before = op(r#"{"op":"before","marker":"前日比"}"#);
+        assert_eq!(before.apply("44,711.43 前日比+225.21"), "44,711.43");
+        assert_eq!(before.apply("44,711.43"), "44,711.43");
+        let after = op(r#"{"op":"after","marker":"前日比"}"#);
+        assert_eq!(after.apply("44,711.43 前日比 +225.21"), "+225.21");
+        assert_eq!(after.apply("44,711.43"), "");
+    }
+
+    #[test]
+    fn between_transform() {
+        let t = op(r#"{"op":"between","start":":","end":"）"}"#);
+        assert_eq!(t.apply("（米国:16:59）"), "16:59");
+        // 片方しかなければそのまま
+        assert_eq!(t.apply("米国16時59分）"), "米国16時59分）");
+        assert_eq!(t.apply("（米国:16:59"), "（米国:16:59");
+    }
+
+    #[test]
+    fn change_transforms() {
+        assert_eq!(op(r#"{"op":"change_abs"}"#).apply("+12.3(+1.02%)"), "+12.3");
+        assert_eq!(op(r#"{"op":"change_pct"}"#).apply("+12.3(+1.02%)"), "+1.02%");
+    }
+
+    #[test]
+    fn transforms_are_applied_in_order() {
+        let rule: FieldRule = serde_json::from_str(
+            r#"{"field":"change_abs","transforms":[{"op":"after","marker":"前日比"},{"op":"before","marker":"リアルタイム"},{"op":"change_abs"}]}"#,
+        )
+        .unwrap();
+        let text = rule.transforms.iter().fold("1,000 前日比-5.5(-0.55%) リアルタイム".to_string(), |text, t| t.apply(&text));
+        assert_eq!(text, "-5.5");
+    }
+
+    fn extract_fixture(html: &str, page_type: PageType) -> Extraction {
+        extract(&Html::parse_document(html), &ExtractionProfile::builtin(page_type).unwrap()).unwrap()
+    }
+
+    fn assert_fields(extraction: &Extraction, expected: &[(&str, &str)]) {
+        for (field, value) in expected {
+            assert_eq!(extraction.get(field), *value, "field {}", field);
+        }
+        assert!(extraction.diagnostics.iter().all(|d| d.status == "found"), "{:#?}", extraction.diagnostics);
+    }
+
+    #[test]
+    fn stock_profile_on_us_stock_fixture() {
+        let extraction = extract_fixture(include_str!("../sample.html"), PageType::Stock);
+        assert_fields(
+            &extraction,
+            &[
+                ("name", "ソニーグループ(株)"),
+                ("code", "SONY"),
+                ("price", "27.75"),
+                ("change_abs", "-0.43"),
+                ("change_pct", "-1.53%"),
+                ("update_time", "10/31 9:04"),
+            ],
+        );
+        let price = extraction.diagnostics.iter().find(|d| d.field == "price").unwrap();
+        assert_eq!(price.selector.as_deref(), Some("span[class*='PriceBoard__price'] span[class*='StyledNumber__value']"));
+    }
+
+    #[test]
+    fn priceboard_profile_on_us_stock_fixture() {
+        // 米国株ページも PriceBoard__main のレイアウトなので、同じ値が取れる
+        let extraction = extract_fixture(include_str!("../sample.html"), PageType::PriceBoard);
+        assert_fields(&extraction, &[("name", "ソニーグループ(株)"), ("price", "27.75"), ("change_abs", "-0.43"), ("change_pct", "-1.53%")]);
+        assert_eq!(extraction.into_stock_data("SONY").code, "SONY");
+    }
+
+    #[test]
+    fn index_profile_on_index_fixture() {
+        let extraction = extract_fixture(include_str!("../DJI.html"), PageType::Index);
+        assert_fields(
+            &extraction,
+            &[("name", "NYダウ"), ("price", "47,522.12"), ("change_abs", "-109.88"), ("change_pct", "-0.23%"), ("update_time", "16:58")],
+        );
+        // 指数のプロファイルはコードを取らないので、リクエストされたコードを使う
+        assert_eq!(extraction.into_stock_data("^DJI").code, "^DJI");
+    }
+
+    #[test]
+    fn missing_container_and_required_fields_are_errors() {
+        let document = Html::parse_document("<div>no board</div>");
+        let stock = ExtractionProfile::builtin(PageType::Stock).unwrap();
+        assert!(extract(&document, &stock).unwrap_err().to_string().contains("Container not found"));
+        let index = ExtractionProfile::builtin(PageType::Index).unwrap();
+        assert!(extract(&document, &index).unwrap_err().to_string().contains("Required field 'price'"));
+    }
+}
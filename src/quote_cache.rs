@@ -0,0 +1,254 @@
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use worker::*;
+
+use crate::update_time::Market;
+
+// --- 取得結果のエッジキャッシュ (Workers Cache API) ---
+// コードごとにキャッシュし、TTL は市場が開いているかどうかで切り替える。
+// 引け後は値が変わらないので長めに持ち、ダッシュボードのポーリングで Yahoo を叩かないようにする。
+
+const TTL_OPEN_VAR: &str = "QUOTE_TTL_OPEN_SECS";
+const TTL_CLOSED_VAR: &str = "QUOTE_TTL_CLOSED_SECS";
+const TTL_FUNDAMENTALS_VAR: &str = "QUOTE_TTL_FUNDAMENTALS_SECS";
+
+const DEFAULT_TTL_OPEN_SECS: u64 = 15;
+const DEFAULT_TTL_CLOSED_SECS: u64 = 600;
+// 時価総額・PER などは取引時間中も数秒単位で追う必要がないので、市場の開閉によらず長めに持つ
+const DEFAULT_TTL_FUNDAMENTALS_SECS: u64 = 3600;
+
+const CACHED_AT_HEADER: &str = "X-Cached-At";
+const CACHE_TTL_HEADER: &str = "X-Cache-Ttl";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheConfig {
+    pub ttl_open: u64,
+    pub ttl_closed: u64,
+    // /fundamentals の TTL
+    pub ttl_fundamentals: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl_open: DEFAULT_TTL_OPEN_SECS,
+            ttl_closed: DEFAULT_TTL_CLOSED_SECS,
+            ttl_fundamentals: DEFAULT_TTL_FUNDAMENTALS_SECS,
+        }
+    }
+}
+
+impl CacheConfig {
+    // wrangler.toml の [vars] から読み込む。未設定や不正な値は既定値を使う
+    pub fn from_ctx(ctx: &RouteContext<()>) -> Self {
+        let read = |name: &str| ctx.var(name).ok().and_then(|v| v.to_string().trim().parse::<u64>().ok());
+        let defaults = CacheConfig::default();
+        CacheConfig {
+            ttl_open: read(TTL_OPEN_VAR).unwrap_or(defaults.ttl_open),
+            ttl_closed: read(TTL_CLOSED_VAR).unwrap_or(defaults.ttl_closed),
+            ttl_fundamentals: read(TTL_FUNDAMENTALS_VAR).unwrap_or(defaults.ttl_fundamentals),
+        }
+    }
+
+    // 取引所が分からないコードは開いているかもしれないので短い方の TTL にする。
+    // 取引時間外でも、次の取引開始を過ぎてまで引け値を返さないよう開始までの秒数で頭打ちにする
+    pub fn ttl_for(&self, code: &str, now: DateTime<Utc>) -> u64 {
+        match Market::for_code(code) {
+            Some(market) if !market.is_open(now) => match market.next_open(now) {
+                Some(open) => self.ttl_closed.min((open - now).num_seconds().max(0) as u64),
+                None => self.ttl_closed,
+            },
+            _ => self.ttl_open,
+        }
+    }
+}
+
+// 1件分のキャッシュ状態。age は取得からの経過秒数、ttl は保存時の有効秒数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStatus {
+    pub hit: bool,
+    pub age: u64,
+    pub ttl: u64,
+}
+
+impl CacheStatus {
+    pub fn remaining(&self) -> u64 {
+        self.ttl.saturating_sub(self.age)
+    }
+}
+
+pub struct QuoteCache {
+    cache: Cache,
+    // キャッシュキーはリクエストと同じオリジンの内部パスにする
+    origin: String,
+    config: CacheConfig,
+    // ?fresh=1 (または true) ならキャッシュを読まずに取り直す (書き込みはする)
+    fresh: bool,
+}
+
+impl QuoteCache {
+    pub fn new(request_url: &Url, config: CacheConfig) -> Self {
+        QuoteCache {
+            cache: Cache::default(),
+            origin: request_url.origin().ascii_serialization(),
+            config,
+            fresh: request_url.query_pairs().any(|(key, value)| key == "fresh" && matches!(value.as_ref(), "1" | "true")),
+        }
+    }
+
+    fn key(&self, kind: &str, code: &str) -> Result<String> {
+        let url = Url::parse_with_params(&format!("{}/__cache/{}", self.origin, kind), &[("code", code)])?;
+        Ok(url.to_string())
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, kind: &str, code: &str) -> Option<(T, CacheStatus)> {
+        if self.fresh {
+            return None;
+        }
+        let key = self.key(kind, code).ok()?;
+        let mut response = match self.cache.get(key, false).await {
+            Ok(Some(r)) => r,
+            Ok(None) => return None,
+            Err(e) => {
+                debug_log!("[QuoteCache] Failed to read {} {}: {}", kind, code, e);
+                return None;
+            }
+        };
+        let header = |name: &str| response.headers().get(name).ok().flatten().and_then(|v| v.parse::<i64>().ok());
+        let cached_at = header(CACHED_AT_HEADER)?;
+        let ttl = header(CACHE_TTL_HEADER)? as u64;
+        let value = response.json::<T>().await.ok()?;
+        let age = (Utc::now().timestamp() - cached_at).max(0) as u64;
+        Some((value, CacheStatus { hit: true, age, ttl }))
+    }
+
+    // get 1回で使うサブリクエスト数 (?fresh=1 ならキャッシュを読まない)
+    pub fn read_cost(&self) -> usize {
+        if self.fresh { 0 } else { 1 }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    // 市場の開閉に応じた TTL で保存する
+    pub async fn put<T: Serialize>(&self, kind: &str, code: &str, value: &T) -> CacheStatus {
+        let ttl = self.config.ttl_for(code, Utc::now());
+        self.put_with_ttl(kind, code, value, ttl).await
+    }
+
+    // 書き込みに失敗しても取得結果はそのまま返したいので、エラーはログに残すだけ
+    pub async fn put_with_ttl<T: Serialize>(&self, kind: &str, code: &str, value: &T, ttl: u64) -> CacheStatus {
+        let now = Utc::now();
+        let status = CacheStatus { hit: false, age: 0, ttl };
+        if ttl == 0 {
+            return status;
+        }
+        let result = async {
+            let key = self.key(kind, code)?;
+            let mut response = Response::from_json(value)?;
+            let headers = response.headers_mut();
+            headers.set("Cache-Control", &format!("public, max-age={}", ttl))?;
+            headers.set(CACHED_AT_HEADER, &now.timestamp().to_string())?;
+            headers.set(CACHE_TTL_HEADER, &ttl.to_string())?;
+            self.cache.put(key, response).await
+        };
+        if let Err(e) = result.await {
+            debug_log!("[QuoteCache] Failed to write {} {}: {}", kind, code, e);
+        }
+        status
+    }
+}
+
+// 複数コードの結果をまとめた Cache-Control / Age の値。
+// 1件でも失敗 (None) があれば、エラーを含む応答をキャッシュさせない
+pub fn response_cache_headers(statuses: &[Option<CacheStatus>]) -> (String, u64) {
+    let age = statuses.iter().flatten().map(|s| s.age).max().unwrap_or(0);
+    if statuses.is_empty() || statuses.iter().any(|s| s.is_none()) {
+        return ("no-store".to_string(), age);
+    }
+    let max_age = statuses.iter().flatten().map(|s| s.remaining()).min().unwrap_or(0);
+    (format!("public, max-age={}", max_age), age)
+}
+
+pub fn apply_cache_headers(mut response: Response, statuses: &[Option<CacheStatus>]) -> Result<Response> {
+    let (cache_control, age) = response_cache_headers(statuses);
+    let headers = response.headers_mut();
+    headers.set("Cache-Control", &cache_control)?;
+    headers.set("Age", &age.to_string())?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn ttl_depends_on_market_hours() {
+        let config = CacheConfig { ttl_open: 10, ttl_closed: 300, ..CacheConfig::default() };
+        // 2024-10-17 (木) 10:00 JST
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-17T01:00:00Z")), 10);
+        // 同時刻のニューヨークは閉まっている
+        assert_eq!(config.ttl_for("^DJI", utc("2024-10-17T01:00:00Z")), 300);
+        // 引け後
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-17T07:00:00Z")), 300);
+        // 取引所の分からない指数
+        assert_eq!(config.ttl_for("^XYZ", utc("2024-10-17T07:00:00Z")), 10);
+    }
+
+    #[test]
+    fn us_stocks_follow_new_york_hours() {
+        let config = CacheConfig { ttl_open: 10, ttl_closed: 600, ..CacheConfig::default() };
+        // 2024-10-17 (木) 15:00Z = 11:00 EDT。東京は閉まっているがニューヨークは取引中
+        assert_eq!(config.ttl_for("SONY", utc("2024-10-17T15:00:00Z")), 10);
+        assert_eq!(config.ttl_for("AAPL", utc("2024-10-17T15:00:00Z")), 10);
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-17T15:00:00Z")), 600);
+        // 寄り付き (09:30 EDT = 13:30Z) の 5 分前は開始までの秒数
+        assert_eq!(config.ttl_for("AAPL", utc("2024-10-17T13:25:00Z")), 300);
+    }
+
+    #[test]
+    fn closed_ttl_ends_at_the_next_open() {
+        let config = CacheConfig { ttl_open: 10, ttl_closed: 600, ..CacheConfig::default() };
+        // 2024-10-17 (木) 08:59 JST: 寄り付きの 09:00 まで 60 秒
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-16T23:59:00Z")), 60);
+        // 昼休みの終わり際 (12:25 JST) も後場の開始まで
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-17T03:25:00Z")), 300);
+        // 金曜の引け後は週明けまで十分あるので ttl_closed のまま
+        assert_eq!(config.ttl_for("7203.T", utc("2024-10-18T07:00:00Z")), 600);
+    }
+
+    #[test]
+    fn fundamentals_have_their_own_longer_ttl() {
+        let config = CacheConfig::default();
+        assert_eq!(config.ttl_fundamentals, DEFAULT_TTL_FUNDAMENTALS_SECS);
+        assert!(config.ttl_fundamentals > config.ttl_open && config.ttl_fundamentals > config.ttl_closed);
+    }
+
+    #[test]
+    fn headers_use_smallest_remaining_ttl_and_oldest_age() {
+        let statuses = [
+            Some(CacheStatus { hit: true, age: 5, ttl: 15 }),
+            Some(CacheStatus { hit: false, age: 0, ttl: 600 }),
+            Some(CacheStatus { hit: true, age: 120, ttl: 600 }),
+        ];
+        assert_eq!(response_cache_headers(&statuses), ("public, max-age=10".to_string(), 120));
+    }
+
+    #[test]
+    fn errors_disable_caching() {
+        let statuses = [Some(CacheStatus { hit: true, age: 3, ttl: 15 }), None];
+        assert_eq!(response_cache_headers(&statuses), ("no-store".to_string(), 3));
+        assert_eq!(response_cache_headers(&[]), ("no-store".to_string(), 0));
+    }
+
+    #[test]
+    fn remaining_never_underflows() {
+        assert_eq!(CacheStatus { hit: true, age: 30, ttl: 15 }.remaining(), 0);
+    }
+}
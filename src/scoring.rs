@@ -0,0 +1,318 @@
+use ego_tree::NodeId;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::OnceLock;
+use worker::*;
+
+// --- 探索候補の特徴量スコアリング ---
+// discover_data の価格・前日比の候補を、名前の付いた特徴量 (クラス名のヒント・見出しとの距離・数値の書式など)
+// と重みで採点する。重みは profiles/scoring.json が既定で、wrangler.toml の DISCOVERY_SCORING (JSON) で差し替えられる。
+// 各特徴量の寄与は RankedCandidate.reason に書き出すので、再コンパイルせずに重みを調整できる。
+
+const DEFAULT_SCORING: &str = include_str!("../profiles/scoring.json");
+const SCORING_VAR: &str = "DISCOVERY_SCORING";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    // 数値の書式 (桁区切りあり 1.0、小数のみ 0.5)
+    NumericFormat,
+    // クラス名にヒント ("value" など) を含む
+    ClassHint,
+    // クラス名に価格でないことを示す語 ("code" "symbol" など) を含む (重みは負にする)
+    NegativeClassHint,
+    // 大きな文字で表示されている (クラス名の "large"、style の font-size)
+    LargeHint,
+    // 銘柄名の見出しに DOM 上で近い
+    NameProximity,
+    // 価格ボードなどのコンテナの中にある
+    Container,
+    // 文書の前の方にある
+    DocumentPosition,
+}
+
+impl Feature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::NumericFormat => "numeric_format",
+            Feature::ClassHint => "class_hint",
+            Feature::NegativeClassHint => "negative_class_hint",
+            Feature::LargeHint => "large_hint",
+            Feature::NameProximity => "name_proximity",
+            Feature::Container => "container",
+            Feature::DocumentPosition => "document_position",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FeatureWeights {
+    pub base: i32,
+    // 載っていない特徴量は使わない
+    #[serde(default)]
+    pub weights: BTreeMap<Feature, i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hints {
+    #[serde(default)]
+    pub class: Vec<String>,
+    #[serde(default)]
+    pub negative_class: Vec<String>,
+    #[serde(default)]
+    pub large_class: Vec<String>,
+    // この値以上の font-size (px) を大きな文字とみなす
+    #[serde(default)]
+    pub large_font_px: u32,
+    #[serde(default)]
+    pub containers: Vec<String>,
+    // 見出しからこのホップ数以上離れていれば近さは 0
+    #[serde(default)]
+    pub max_proximity_hops: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoringConfig {
+    pub price: FeatureWeights,
+    pub change: FeatureWeights,
+    #[serde(default)]
+    pub hints: Hints,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        serde_json::from_str(DEFAULT_SCORING).expect("profiles/scoring.json is valid")
+    }
+}
+
+impl ScoringConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::from(format!("Invalid scoring config: {}", e)))
+    }
+
+    // wrangler.toml の [vars] から読み込む。未設定なら既定値、不正な JSON ならログを残して既定値
+    pub fn from_ctx(ctx: &RouteContext<()>) -> Self {
+        let Ok(var) = ctx.var(SCORING_VAR) else {
+            return Self::default();
+        };
+        match Self::from_json(&var.to_string()) {
+            Ok(config) => config,
+            Err(e) => {
+                debug_log!("[Scoring] {}: {}, using defaults", SCORING_VAR, e);
+                Self::default()
+            }
+        }
+    }
+}
+
+// 特徴量1つ分の寄与
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub feature: Feature,
+    pub points: i32,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scored {
+    pub score: u32,
+    pub contributions: Vec<Contribution>,
+}
+
+impl Scored {
+    // "base 50; numeric_format +30; class_hint +20 (value)" の形
+    pub fn explain(&self, base: i32) -> String {
+        let mut parts = vec![format!("base {}", base)];
+        for c in &self.contributions {
+            let detail = c.detail.as_ref().map(|d| format!(" ({})", d)).unwrap_or_default();
+            parts.push(format!("{} {:+}{}", c.feature.as_str(), c.points, detail));
+        }
+        parts.join("; ")
+    }
+}
+
+/// 1ページ分の採点器。見出し・コンテナ・文書順は最初に1度だけ調べる
+pub struct Scorer<'a> {
+    config: &'a ScoringConfig,
+    // 見出しの祖先ごとの、見出しからの距離
+    anchor: HashMap<NodeId, u32>,
+    containers: HashSet<NodeId>,
+    order: HashMap<NodeId, usize>,
+}
+
+impl<'a> Scorer<'a> {
+    /// name を含む最初の見出し (h1, h2) を近さの基準にする
+    pub fn new(document: &Html, config: &'a ScoringConfig, name: &str) -> Self {
+        let mut anchor = HashMap::new();
+        if let (Ok(headings), false) = (Selector::parse("h1, h2"), name.is_empty()) {
+            if let Some(heading) = document.select(&headings).find(|h| h.text().collect::<String>().contains(name)) {
+                anchor.insert(heading.id(), 0);
+                for (hops, ancestor) in heading.ancestors().enumerate() {
+                    anchor.insert(ancestor.id(), hops as u32 + 1);
+                }
+            }
+        }
+        let containers = config
+            .hints
+            .containers
+            .iter()
+            .filter_map(|s| Selector::parse(s).ok())
+            .flat_map(|sel| document.select(&sel).map(|el| el.id()).collect::<Vec<_>>())
+            .collect();
+        let order = Selector::parse("*")
+            .map(|all| document.select(&all).enumerate().map(|(i, el)| (el.id(), i)).collect())
+            .unwrap_or_default();
+        Scorer { config, anchor, containers, order }
+    }
+
+    pub fn score(&self, weights: &FeatureWeights, element: ElementRef, text: &str) -> Scored {
+        let mut contributions = Vec::new();
+        let mut total = weights.base;
+        for (&feature, &weight) in &weights.weights {
+            let Some((strength, detail)) = self.measure(feature, element, text) else {
+                continue;
+            };
+            let points = (weight as f64 * strength).round() as i32;
+            if points != 0 {
+                total += points;
+                contributions.push(Contribution { feature, points, detail });
+            }
+        }
+        Scored { score: total.max(0) as u32, contributions }
+    }
+
+    // 特徴量の強さ (0.0〜1.0) と説明。当てはまらなければ None
+    fn measure(&self, feature: Feature, element: ElementRef, text: &str) -> Option<(f64, Option<String>)> {
+        let hints = &self.config.hints;
+        let class = element.value().attr("class").unwrap_or("");
+        let find_hint = |words: &[String]| words.iter().find(|w| class.contains(w.as_str())).cloned();
+        match feature {
+            Feature::NumericFormat => numeric_format(text).map(|s| (s, None)),
+            Feature::ClassHint => find_hint(&hints.class).map(|w| (1.0, Some(w))),
+            Feature::NegativeClassHint => find_hint(&hints.negative_class).map(|w| (1.0, Some(w))),
+            Feature::LargeHint => {
+                // 要素自身か親のどちらかが大きければよい
+                let els = std::iter::once(element).chain(element.parent().and_then(ElementRef::wrap));
+                els.into_iter().find_map(|el| {
+                    let class = el.value().attr("class").unwrap_or("");
+                    if let Some(w) = hints.large_class.iter().find(|w| class.contains(w.as_str())) {
+                        return Some((1.0, Some(w.clone())));
+                    }
+                    let px = font_size_px(el.value().attr("style").unwrap_or(""))?;
+                    (hints.large_font_px > 0 && px >= hints.large_font_px).then(|| (1.0, Some(format!("{}px", px))))
+                })
+            }
+            Feature::NameProximity => {
+                let hops = std::iter::once(element.id())
+                    .chain(element.ancestors().map(|a| a.id()))
+                    .enumerate()
+                    .find_map(|(up, id)| self.anchor.get(&id).map(|down| up as u32 + down))?;
+                let max = hints.max_proximity_hops.max(1);
+                (hops < max).then(|| (1.0 - hops as f64 / max as f64, Some(format!("{} hops", hops))))
+            }
+            Feature::Container => element.ancestors().any(|a| self.containers.contains(&a.id())).then_some((1.0, None)),
+            Feature::DocumentPosition => {
+                let index = *self.order.get(&element.id())?;
+                Some((1.0 - index as f64 / self.order.len().max(1) as f64, None))
+            }
+        }
+    }
+}
+
+fn grouped_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^[+\-]?\d{1,3}(?:,\d{3})+(?:\.\d+)?$").unwrap())
+}
+
+fn decimal_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^[+\-]?\d+\.\d+$").unwrap())
+}
+
+// "(+1.02%)" のような括弧とパーセントは外して判定する
+fn numeric_format(text: &str) -> Option<f64> {
+    let core = text.trim().trim_start_matches('(').trim_end_matches(')').trim_end_matches('%');
+    if grouped_regex().is_match(core) {
+        Some(1.0)
+    } else if decimal_regex().is_match(core) {
+        Some(0.5)
+    } else {
+        None
+    }
+}
+
+fn font_size_px(style: &str) -> Option<u32> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"font-size\s*:\s*(\d+(?:\.\d+)?)px").unwrap());
+    re.captures(style)?[1].parse::<f64>().ok().map(|px| px as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><body>
+        <div class="PriceBoard__main">
+          <h2>トヨタ自動車(株)</h2>
+          <span class="code">7203</span>
+          <span class="StyledNumber__value" style="font-size: 28px">2,862.5</span>
+        </div>
+        <footer><div><div><div><div><span>1234</span></div></div></div></div></footer>
+      </body></html>"#;
+
+    fn element<'a>(document: &'a Html, selector: &str) -> ElementRef<'a> {
+        document.select(&Selector::parse(selector).unwrap()).next().unwrap()
+    }
+
+    #[test]
+    fn default_config_loads() {
+        let config = ScoringConfig::default();
+        assert_eq!(config.price.weights[&Feature::NegativeClassHint], -40);
+        assert!(ScoringConfig::from_json("{").is_err());
+    }
+
+    #[test]
+    fn price_features_and_explanation() {
+        let document = Html::parse_document(PAGE);
+        let config = ScoringConfig::default();
+        let scorer = Scorer::new(&document, &config, "トヨタ自動車(株)");
+
+        let price = scorer.score(&config.price, element(&document, "span.StyledNumber__value"), "2,862.5");
+        let features: Vec<Feature> = price.contributions.iter().map(|c| c.feature).collect();
+        assert!(features.contains(&Feature::NumericFormat));
+        assert!(features.contains(&Feature::ClassHint));
+        assert!(features.contains(&Feature::LargeHint));
+        assert!(features.contains(&Feature::Container));
+        let explained = price.explain(config.price.base);
+        assert!(explained.starts_with("base 50; "));
+        assert!(explained.contains("large_hint +10 (28px)"));
+        assert!(explained.contains("name_proximity +"));
+
+        let code = scorer.score(&config.price, element(&document, "span.code"), "7203");
+        assert!(code.contributions.iter().any(|c| c.feature == Feature::NegativeClassHint && c.points == -40));
+        let far = scorer.score(&config.price, element(&document, "footer span"), "1234");
+        assert!(price.score > far.score && far.score > code.score);
+    }
+
+    #[test]
+    fn weights_come_from_config() {
+        let document = Html::parse_document(PAGE);
+        let config = ScoringConfig::from_json(r#"{"price":{"base":5,"weights":{"document_position":100}},"change":{"base":0}}"#).unwrap();
+        let scorer = Scorer::new(&document, &config, "");
+        let scored = scorer.score(&config.price, element(&document, "footer span"), "1234");
+        // 見出しやヒントは使われず、文書順だけで採点される
+        assert_eq!(scored.contributions.len(), 1);
+        assert_eq!(scored.contributions[0].feature, Feature::DocumentPosition);
+        assert!(scored.score > 5 && scored.score < 105);
+    }
+
+    #[test]
+    fn numeric_formats() {
+        assert_eq!(numeric_format("1,234.5"), Some(1.0));
+        assert_eq!(numeric_format("(+1.02%)"), Some(0.5));
+        assert_eq!(numeric_format("7203"), None);
+        assert_eq!(font_size_px("color: red; font-size: 24.5px"), Some(24));
+    }
+}
@@ -0,0 +1,294 @@
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde_json::Value;
+use std::sync::OnceLock;
+
+// --- <script> に埋め込まれた状態 JSON の抽出 ---
+// Yahoo のページは window.__PRELOADED_STATE__ = {...} の形で表示用のデータを丸ごと埋め込んでいる。
+// 正規表現の \{.*?\}; ではネストした JSON の途中の "};" で切れてしまうので、
+// 括弧の対応 (文字列リテラル内は無視) をたどって代入の右辺を切り出す。
+// 値は "__PRELOADED_STATE__.mainStocksPriceBoard.price" のようなパスで参照する。
+
+#[derive(Debug, Clone)]
+pub struct ScriptState {
+    // 代入先の名前 ("window." などは除く)。<script type="application/json"> なら id
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScriptStates(Vec<ScriptState>);
+
+impl ScriptStates {
+    pub fn from_document(document: &Html) -> Self {
+        let Ok(scripts) = Selector::parse("script") else {
+            return Self::default();
+        };
+        let mut states = Vec::new();
+        for script in document.select(&scripts) {
+            let text = script.text().collect::<String>();
+            let attrs = script.value();
+            if attrs.attr("type") == Some("application/json") {
+                // <script id="__NEXT_DATA__" type="application/json"> は中身全体が JSON
+                if let (Some(id), Ok(value)) = (attrs.attr("id"), serde_json::from_str(&text)) {
+                    states.push(ScriptState { name: id.to_string(), value });
+                }
+                continue;
+            }
+            states.extend(find_assignments(&text));
+        }
+        ScriptStates(states)
+    }
+
+    pub fn states(&self) -> &[ScriptState] {
+        &self.0
+    }
+
+    /// "状態名.キー.キー[0]" の形のパスで値を探す。キーには "*PriceBoard" のようなワイルドカードも使える
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        self.0.iter().find_map(|state| {
+            let rest = path.strip_prefix(state.name.as_str())?;
+            if !(rest.is_empty() || rest.starts_with('.') || rest.starts_with('[')) {
+                return None;
+            }
+            lookup(&state.value, rest.strip_prefix('.').unwrap_or(rest))
+        })
+    }
+
+    /// get() の結果を文字列にする。オブジェクト・配列・null は None
+    pub fn text(&self, path: &str) -> Option<String> {
+        match self.get(path)? {
+            Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl ScriptStates {
+    /// 末端の値 (文字列・数値) が wanted を満たすパスをすべて返す (状態の順・キー順)。
+    /// パスで表せないキー ("." や "[" を含むなど) の下は探さない
+    pub fn find_paths(&self, wanted: &dyn Fn(&Value) -> bool) -> Vec<String> {
+        let mut found = Vec::new();
+        for state in &self.0 {
+            collect_paths(&state.value, &mut state.name.clone(), wanted, &mut found);
+        }
+        found
+    }
+}
+
+fn collect_paths(value: &Value, path: &mut String, wanted: &dyn Fn(&Value) -> bool, found: &mut Vec<String>) {
+    let len = path.len();
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                if key.is_empty() || key.contains(['.', '[', ']', '*']) {
+                    continue;
+                }
+                path.push('.');
+                path.push_str(key);
+                collect_paths(child, path, wanted, found);
+                path.truncate(len);
+            }
+        }
+        Value::Array(items) => {
+            for (i, child) in items.iter().enumerate() {
+                path.push_str(&format!("[{}]", i));
+                collect_paths(child, path, wanted, found);
+                path.truncate(len);
+            }
+        }
+        Value::String(_) | Value::Number(_) if wanted(value) => found.push(path.clone()),
+        _ => {}
+    }
+}
+
+fn assignment_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // name = { / name = [ (window.name・var name なども同じ)
+    RE.get_or_init(|| Regex::new(r"((?:[A-Za-z_$][\w$]*\.)*[A-Za-z_$][\w$]*)\s*=\s*[\[{]").unwrap())
+}
+
+/// スクリプト中の `name = {...}` / `name = [...]` のうち、右辺が JSON として読めるものを返す
+pub fn find_assignments(script: &str) -> Vec<ScriptState> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while let Some(caps) = assignment_regex().captures_at(script, pos) {
+        // 正規表現の末尾が開き括弧。"a == {" などの比較は "=" の直後が括弧でないので一致しない
+        let open = caps.get(0).map_or(pos, |m| m.end() - 1);
+        let parsed = balanced_end(script, open)
+            .and_then(|end| serde_json::from_str::<Value>(&script[open..end]).ok().map(|value| (end, value)));
+        match parsed {
+            Some((end, value)) => {
+                let name = caps[1].trim_start_matches("window.").trim_start_matches("self.").trim_start_matches("globalThis.");
+                found.push(ScriptState { name: name.to_string(), value });
+                pos = end;
+            }
+            // JS のオブジェクトリテラル (キーに引用符がないなど) は読み飛ばす
+            None => pos = open + 1,
+        }
+    }
+    found
+}
+
+/// text[start] の括弧に対応する閉じ括弧の次の位置。文字列リテラル内の括弧は数えない
+pub fn balanced_end(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<u8> = None;
+    let mut escaped = false;
+    for (i, &b) in text.as_bytes().iter().enumerate().skip(start) {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == q {
+                quote = None;
+            }
+            continue;
+        }
+        match b {
+            b'"' | b'\'' => quote = Some(b),
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+// "a.b[0].c" => [Key(a), Key(b), Index(0), Key(c)]。先頭の "$." は省略できる
+fn segments(path: &str) -> Option<Vec<Segment>> {
+    let path = path.strip_prefix('$').unwrap_or(path).trim_start_matches('.');
+    let mut out = Vec::new();
+    for part in path.split('.').filter(|p| !p.is_empty()) {
+        let (key, mut rest) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if !key.is_empty() {
+            out.push(Segment::Key(key.to_string()));
+        }
+        while let Some(inner) = rest.strip_prefix('[') {
+            let close = inner.find(']')?;
+            out.push(Segment::Index(inner[..close].trim().parse().ok()?));
+            rest = &inner[close + 1..];
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(out)
+}
+
+/// JSON パスで値を探す。"*" を含むキーは最初に一致したキーから順に試す
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    walk(value, &segments(path)?)
+}
+
+fn walk<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    let Some((first, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    match first {
+        Segment::Index(i) => walk(value.as_array()?.get(*i)?, rest),
+        Segment::Key(key) if key.contains('*') => {
+            value.as_object()?.iter().filter(|(k, _)| glob_match(key, k)).find_map(|(_, v)| walk(v, rest))
+        }
+        Segment::Key(key) => walk(value.as_object()?.get(key)?, rest),
+    }
+}
+
+// "*" だけを解釈する単純なワイルドカード照合
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !text.starts_with(first) || text.len() < first.len() + last.len() || !text.ends_with(last) {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn states(html: &str) -> ScriptStates {
+        ScriptStates::from_document(&Html::parse_document(html))
+    }
+
+    #[test]
+    fn nested_json_is_not_cut_at_the_first_closing_brace() {
+        let page = states(
+            r#"<script>window.__PRELOADED_STATE__ = {"a":{"b":1},"s":"};{","board":{"price":"2,862.5"}};
+               window.other = 1;</script>"#,
+        );
+        assert_eq!(page.text("__PRELOADED_STATE__.board.price").as_deref(), Some("2,862.5"));
+        assert_eq!(page.text("__PRELOADED_STATE__.s").as_deref(), Some("};{"));
+        assert_eq!(page.get("__PRELOADED_STATE__.a"), Some(&json!({"b": 1})));
+    }
+
+    #[test]
+    fn several_assignments_and_json_scripts() {
+        let page = states(
+            r#"<script>var cfg = {ttl: 1}; self.__DATA__=[{"k":"v"}]; if (x == {}) {}</script>
+               <script id="__NEXT_DATA__" type="application/json">{"props":{"pageProps":{"code":"7203.T"}}}</script>"#,
+        );
+        let names: Vec<&str> = page.states().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["__DATA__", "__NEXT_DATA__"]);
+        assert_eq!(page.text("__DATA__[0].k").as_deref(), Some("v"));
+        assert_eq!(page.text("__NEXT_DATA__.props.pageProps.code").as_deref(), Some("7203.T"));
+        assert_eq!(page.text("__NEXT_DATA__.props"), None);
+        assert_eq!(page.get("__NEXT_DATA_.props"), None);
+    }
+
+    #[test]
+    fn paths_with_wildcards_and_indexes() {
+        let value = json!({"mainFxPriceBoard": {"price": 150.25}, "list": [[1, 2], [3]]});
+        assert_eq!(lookup(&value, "*PriceBoard.price"), Some(&json!(150.25)));
+        assert_eq!(lookup(&value, "$.list[1][0]"), Some(&json!(3)));
+        assert_eq!(lookup(&value, "list[2]"), None);
+        assert_eq!(lookup(&value, "list[x]"), None);
+        assert!(glob_match("main*Board", "mainUsStocksPriceBoard"));
+        assert!(!glob_match("*PriceBoard", "priceBoard"));
+    }
+
+    #[test]
+    fn unbalanced_text() {
+        assert_eq!(balanced_end(r#"{"a":[1,2}"#, 0), None);
+        assert_eq!(balanced_end(r#"x = {"a":"\"}"} tail"#, 4), Some(15));
+    }
+
+    #[test]
+    fn paths_of_matching_values() {
+        let page = states(r#"<script>window.S = {"board":{"price":"27.75","list":[{"v":27.75}]},"a.b":{"price":"27.75"}};</script>"#);
+        let paths = page.find_paths(&|v| v.as_str() == Some("27.75") || v.as_f64() == Some(27.75));
+        assert_eq!(paths, vec!["S.board.list[0].v", "S.board.price"]);
+        assert!(paths.iter().all(|p| page.get(p).is_some()));
+    }
+
+    #[test]
+    fn us_stock_fixture() {
+        let page = states(include_str!("../sample.html"));
+        assert_eq!(page.text("__PRELOADED_STATE__.mainUsStocksPriceBoard.price").as_deref(), Some("27.75"));
+        assert_eq!(page.text("__PRELOADED_STATE__.*PriceBoard.priceChangeRate").as_deref(), Some("-1.53"));
+        assert_eq!(page.text("__PRELOADED_STATE__.pageInfo.code").as_deref(), Some("SONY"));
+    }
+}
@@ -1,21 +1,136 @@
 use scraper::{Element, Html, ElementRef, Selector};
+use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::label::{label_rules_for, LabelRule};
+use crate::parsing::{parse_decimal, parse_percent};
+use crate::script_state::ScriptStates;
+use crate::text_match::TextMatcher;
+
+// どの手がかりからセレクターを組み立てたか
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectorStrategy {
+    Id,
+    Class,
+    BemPrefix,
+    ParentContext,
+    // ビルドハッシュを除いたクラス名の部分一致 (例: [class*='_StyledNumber__value_'])
+    ClassPrefix,
+    Attribute,
+    Tag,
+    // 埋め込み JSON (__PRELOADED_STATE__ など) のパス。selector は "json:<パス>" (FieldRule.json に使える)
+    JsonPath,
+}
+
+// 検証済みのセレクター候補。match_count はドキュメント全体での一致数 (1 なら対象要素だけに一致)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SelectorCandidate {
+    pub selector: String,
+    pub score: u32,
+    // 次のデプロイ後も使えそうか (0-100)。ビルドハッシュ付きのクラスや ID をそのまま含むと低い
+    pub stability: u32,
+    pub strategy: SelectorStrategy,
+    pub match_count: usize,
+    pub reason: String,
+}
+
+impl SelectorCandidate {
+    pub fn is_unique(&self) -> bool {
+        self.match_count == 1
+    }
+
+    // 並べ替えに使う値。スコアを安定度で割り引く
+    pub fn rank(&self) -> u32 {
+        self.score * self.stability / 100
+    }
+}
+
+const STABLE: u32 = 100;
+// 部分一致は別のクラスにも当たりうるので、完全一致よりわずかに下げる
+const SUBSTRING: u32 = 90;
+const HASHED: u32 = 20;
+
+// 生成段階の候補
+struct Generated {
+    score: u32,
+    stability: u32,
+    strategy: SelectorStrategy,
+    reason: String,
+}
+
 // セレクター候補を生成するメイン関数
-pub fn generate_selector_candidates(html_str: &str, target_text: &str) -> Vec<String> {
+pub fn generate_selector_candidates(html_str: &str, target_text: &str) -> Vec<SelectorCandidate> {
     let document = Html::parse_document(html_str);
-    // セレクター文字列をキー、最高スコアを値とするHashMapを使用
-    let mut candidate_map: HashMap<String, u32> = HashMap::new();
+    generate_selector_candidates_in(&document, &TextMatcher::exact(target_text))
+}
+
+// 解析済みのドキュメントからセレクター候補を生成する (同じページで何度も呼ぶ場合はこちら)
+pub fn generate_selector_candidates_in(document: &Html, target: &TextMatcher) -> Vec<SelectorCandidate> {
+    match find_target(document, target) {
+        Some(target) => candidates_for(document, target),
+        None => Vec::new(),
+    }
+}
+
+// 対象要素1つ分の候補を生成し、検証して並べる
+fn candidates_for(document: &Html, target: ElementRef) -> Vec<SelectorCandidate> {
+    // セレクター文字列をキー、最高スコアの候補を値とするHashMapを使用
+    let mut candidate_map: HashMap<String, Generated> = HashMap::new();
+    // HashMapを渡してセレクターを生成
+    generate_for_element(target, &mut candidate_map);
+
+    // 生成したセレクターを実際にドキュメントに当て、対象要素に当たらないものは捨てる
+    let mut verified: Vec<_> = candidate_map
+        .into_iter()
+        .filter_map(|(selector, generated)| {
+            let match_count = verify_selector(document, &selector, target)?;
+            let reason = if match_count == 1 {
+                format!("{}; unique match", generated.reason)
+            } else {
+                format!("{}; first of {} matches", generated.reason, match_count)
+            };
+            Some(SelectorCandidate {
+                selector,
+                score: generated.score,
+                stability: generated.stability,
+                strategy: generated.strategy,
+                match_count,
+                reason,
+            })
+        })
+        .collect();
+
+    sort_candidates(&mut verified);
+    verified
+}
+
+// 対象だけに一致するものを優先し、その中で安定度を加味したスコアの降順 (同点ならセレクター順で安定させる)
+fn sort_candidates(candidates: &mut [SelectorCandidate]) {
+    candidates.sort_by(|a, b| {
+        b.is_unique()
+            .cmp(&a.is_unique())
+            .then(b.rank().cmp(&a.rank()))
+            .then(b.score.cmp(&a.score))
+            .then_with(|| a.selector.cmp(&b.selector))
+    });
+}
+
+// 対象を含む要素のうち、テキストが最も短い (= 最も内側の) 要素
+fn find_target<'a>(document: &'a Html, target: &TextMatcher) -> Option<ElementRef<'a>> {
     let mut best_match: Option<(ElementRef, usize)> = None;
 
     if let Ok(selector) = Selector::parse("*") {
-        for element in document.select(&selector) {
+        for element in document.select(&selector).filter(|el| is_rendered(*el)) {
             let element_text = element.text().collect::<String>();
-            if element_text.contains(target_text) {
+            if target.matches(&element_text) {
                 let text_len = element_text.len();
                 // Update best_match if none exists or current text is shorter than previous best
+                // (同じ長さなら内側の要素を優先する: <div><p>100</p></div> なら p)
                 let should_replace = match best_match.as_ref() {
-                    Some((_, prev_len)) => text_len < *prev_len,
+                    Some((prev, prev_len)) => {
+                        text_len < *prev_len || (text_len == *prev_len && element.ancestors().any(|a| a.id() == prev.id()))
+                    }
                     None => true,
                 };
                 if should_replace {
@@ -25,55 +140,552 @@ pub fn generate_selector_candidates(html_str: &str, target_text: &str) -> Vec<St
         }
     }
 
-    if let Some((element, _)) = best_match {
-        // HashMapを渡してセレクターを生成
-        generate_for_element(element, &mut candidate_map);
+    best_match.map(|(element, _)| element)
+}
+
+// <script> の中の JSON は表示されないので対象要素にしない (値の場所は json_path_candidates で返す)
+fn is_rendered(element: ElementRef) -> bool {
+    !matches!(element.value().name(), "script" | "style")
+}
+
+// セレクターを再パースしてドキュメントに当て、最初の一致が対象要素なら一致数を返す。
+// パースできない・対象に当たらない・先に別の要素に当たる場合は None
+fn verify_selector(document: &Html, selector: &str, target: ElementRef) -> Option<usize> {
+    let parsed = Selector::parse(selector).ok()?;
+    let mut matches = document.select(&parsed);
+    if matches.next()?.id() != target.id() {
+        return None;
+    }
+    Some(1 + matches.count())
+}
+
+// --- 同じテキストを含む複数の要素 ---
+// 価格の文字列はヘッダー・株価ボード・詳細テーブルなど複数箇所に出ることがある。
+// 最短テキストの1要素に決め打ちせず、該当する要素ごとに候補をまとめて返し、呼び出し側に選ばせる。
+
+// region として扱う祖先要素
+const LANDMARKS: &[&str] = &["header", "nav", "main", "aside", "footer", "article", "section", "table", "form"];
+const CONTEXT_MAX_CHARS: usize = 80;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TargetGroup {
+    // 文書順での番号 (絞り込み前の番号をそのまま使う)
+    pub index: usize,
+    // "html > body > div#main > section:nth-of-type(2) > span.value" のような位置 (埋め込み JSON だけのグループは空)
+    pub dom_path: String,
+    // 最も近いランドマーク要素 ("header", "table" など)。なければ None
+    pub region: Option<String>,
+    // 親要素のテキスト (前後の表示を見て見分けるため)
+    pub context: String,
+    // 要素のテキストが対象と完全に一致するか (前後の空白は無視)
+    pub exact: bool,
+    // グループの評価。最上位候補の rank に、完全一致なら加点する
+    pub score: u32,
+    pub candidates: Vec<SelectorCandidate>,
+    // 値が複数のノードに分かれている場合の組み立て方 (candidates はその共通の親に対するもの)
+    pub composite: Option<CompositeValue>,
+    // 近くのラベル (「始値」など) から値をたどるルール。クラス名が変わっても使える
+    pub label_rules: Vec<LabelRule>,
+}
+
+// どのグループを返すか
+#[derive(Debug, Clone)]
+pub enum TargetFilter {
+    All,
+    Index(usize),
+    Region(String),
+    // このセレクターに一致する要素の内側にあるものだけ
+    Within(Selector),
+}
+
+/// 対象を含む最も内側の要素をすべて探し、要素ごとに候補を生成する。
+/// 表示されている要素がなく埋め込み JSON にだけ値がある場合は、JSON のパスだけのグループを1つ返す
+pub fn generate_selector_groups_in(document: &Html, target: &TextMatcher, filter: &TargetFilter) -> Vec<TargetGroup> {
+    let containers: Vec<_> = match filter {
+        TargetFilter::Within(scope) => document.select(scope).map(|el| el.id()).collect(),
+        _ => Vec::new(),
+    };
+    // 埋め込み JSON にある同じ値は、どの要素のグループにも候補として並べる
+    let json_candidates = json_path_candidates(&ScriptStates::from_document(document), target);
+    let targets = find_targets(document, target);
+    if targets.is_empty() {
+        // JSON はどのランドマークの内側でもないので、region / within で絞り込んだときは返さない
+        let wanted = matches!(filter, TargetFilter::All | TargetFilter::Index(0));
+        return (wanted && !json_candidates.is_empty()).then(|| json_only_group(json_candidates)).into_iter().collect();
+    }
+    targets
+        .into_iter()
+        .enumerate()
+        .filter(|(index, element)| match filter {
+            TargetFilter::All => true,
+            TargetFilter::Index(wanted) => index == wanted,
+            TargetFilter::Region(wanted) => region_of(*element).as_deref() == Some(wanted.as_str()),
+            TargetFilter::Within(_) => element.ancestors().any(|a| containers.contains(&a.id())),
+        })
+        .map(|(index, element)| {
+            let mut candidates = candidates_for(document, element);
+            candidates.extend(json_candidates.iter().cloned());
+            sort_candidates(&mut candidates);
+            let exact = target.matches_whole(&element.text().collect::<String>());
+            let best = candidates.first().map_or(0, |c| c.rank());
+            TargetGroup {
+                index,
+                dom_path: dom_path(element),
+                region: region_of(element),
+                context: context_of(element),
+                exact,
+                score: if exact { best + 10 } else { best },
+                candidates,
+                composite: describe_composite(element, target),
+                label_rules: label_rules_for(document, element),
+            }
+        })
+        .collect()
+}
+
+// 埋め込み JSON の値は文字列全体で照合しているので完全一致として扱う
+fn json_only_group(mut candidates: Vec<SelectorCandidate>) -> TargetGroup {
+    sort_candidates(&mut candidates);
+    let best = candidates.first().map_or(0, |c| c.rank());
+    TargetGroup {
+        index: 0,
+        dom_path: String::new(),
+        region: None,
+        context: String::new(),
+        exact: true,
+        score: best + 10,
+        candidates,
+        composite: None,
+        label_rules: Vec::new(),
+    }
+}
+
+// --- 埋め込み JSON のパス ---
+// Yahoo のページでは表示と同じ値が __PRELOADED_STATE__ にも入っており、クラス名のようにデプロイで変わらない。
+// DOM のセレクターと同じ基準で並べられるよう SelectorCandidate として返す。
+
+const JSON_PATH_SCORE: u32 = 95;
+// 配列の位置は並び順が変わるとずれるので、添字1つごとに安定度を下げる
+const JSON_INDEX_PENALTY: u32 = 20;
+// 同じ値が大量に入っている JSON (ランキング一覧など) で候補が膨らまないようにする
+const MAX_JSON_CANDIDATES: usize = 10;
+
+/// 埋め込み JSON のうち値が対象と一致する末端のパス。文字列は全体一致、数値は数値として比較する
+pub fn json_path_candidates(states: &ScriptStates, target: &TextMatcher) -> Vec<SelectorCandidate> {
+    let matches = |value: &serde_json::Value| match value {
+        serde_json::Value::String(text) => target.matches_whole(text),
+        serde_json::Value::Number(n) => parse_decimal(&n.to_string()).is_some_and(|n| target.matches_number(n)),
+        _ => false,
+    };
+    let mut candidates: Vec<SelectorCandidate> = states
+        .find_paths(&matches)
+        .into_iter()
+        .map(|path| {
+            let indexes = path.matches('[').count() as u32;
+            SelectorCandidate {
+                selector: format!("json:{}", path),
+                score: JSON_PATH_SCORE,
+                stability: STABLE.saturating_sub(indexes * JSON_INDEX_PENALTY).max(HASHED),
+                strategy: SelectorStrategy::JsonPath,
+                // パスは常に1つの値を指す
+                match_count: 1,
+                reason: format!("Embedded state value at {}", path),
+            }
+        })
+        .collect();
+    sort_candidates(&mut candidates);
+    candidates.truncate(MAX_JSON_CANDIDATES);
+    candidates
+}
+
+// テキストを含む要素のうち、子要素にはそのテキストを含まないもの (文書順)
+fn find_targets<'a>(document: &'a Html, target: &TextMatcher) -> Vec<ElementRef<'a>> {
+    let Ok(all) = Selector::parse("*") else {
+        return Vec::new();
+    };
+    document
+        .select(&all)
+        .filter(|el| is_rendered(*el))
+        .filter(|el| target.matches(&el.text().collect::<String>()))
+        .filter(|el| !el.child_elements().any(|child| target.matches(&child.text().collect::<String>())))
+        .collect()
+}
+
+fn dom_path(element: ElementRef) -> String {
+    let mut parts: Vec<String> = std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .map(|el| {
+            let name = el.value().name();
+            if let Some(id) = el.value().id() {
+                return format!("{}#{}", name, id);
+            }
+            let same_tag: Vec<_> = el
+                .parent()
+                .into_iter()
+                .flat_map(|p| p.children().filter_map(ElementRef::wrap))
+                .filter(|sibling| sibling.value().name() == name)
+                .collect();
+            if same_tag.len() > 1 {
+                let nth = same_tag.iter().position(|sibling| sibling.id() == el.id()).unwrap_or(0) + 1;
+                format!("{}:nth-of-type({})", name, nth)
+            } else {
+                name.to_string()
+            }
+        })
+        .collect();
+    parts.reverse();
+    parts.join(" > ")
+}
+
+fn region_of(element: ElementRef) -> Option<String> {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .map(|el| el.value().name())
+        .find(|name| LANDMARKS.contains(name))
+        .map(str::to_string)
+}
+
+fn context_of(element: ElementRef) -> String {
+    let scope = element.parent_element().unwrap_or(element);
+    let text = scope.text().collect::<Vec<_>>().join(" ");
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.chars().take(CONTEXT_MAX_CHARS).collect()
+}
+
+// --- 複数のノードに分かれた値 ---
+// "+12.3(+1.02%)" は符号・数値・括弧・% がそれぞれ別の span に入っていることがある。
+// 対象を含む最も内側の要素 (= 部品の共通の親) の中で、どの部品がどの順で並ぶかを返す。
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PieceKind {
+    Number,
+    Sign,
+    Text,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValuePiece {
+    pub kind: PieceKind,
+    // 例として渡されたページでのテキスト
+    pub text: String,
+    // 共通の親からの相対セレクター。親の直下のテキストなら None
+    pub selector: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CompositeValue {
+    pub pieces: Vec<ValuePiece>,
+    // {n} を pieces[n] のテキストに置き換えると値になる。括弧や % などの記号はそのまま含む
+    pub template: String,
+}
+
+/// container の中で対象が2つ以上のテキストノードにまたがっていれば、その組み立て方を返す
+pub fn describe_composite(container: ElementRef, target: &TextMatcher) -> Option<CompositeValue> {
+    // (テキスト, テキストを持つ要素)
+    let segments: Vec<(&str, Option<ElementRef>)> = container
+        .descendants()
+        .filter_map(|node| {
+            let text = node.value().as_text()?;
+            Some((&**text, node.parent().and_then(ElementRef::wrap)))
+        })
+        .collect();
+    let joined = |range: &[(&str, Option<ElementRef>)]| range.iter().map(|(t, _)| *t).collect::<String>();
+
+    // 対象に関係しない前後の部品 (ラベルなど) を、一致が崩れない範囲で削る
+    let (mut start, mut end) = (0, segments.len());
+    while start + 1 < end && target.matches(&joined(&segments[start + 1..end])) {
+        start += 1;
+    }
+    while end > start + 1 && target.matches(&joined(&segments[start..end - 1])) {
+        end -= 1;
+    }
+    let range = &segments[start..end];
+    if range.iter().filter(|(t, _)| !t.trim().is_empty()).count() < 2 {
+        return None;
+    }
+
+    let mut pieces = Vec::new();
+    let mut template = String::new();
+    for (text, owner) in range {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            template.push(' ');
+            continue;
+        }
+        let Some(kind) = piece_kind(trimmed) else {
+            template.push_str(trimmed);
+            continue;
+        };
+        template.push_str(&format!("{{{}}}", pieces.len()));
+        pieces.push(ValuePiece {
+            kind,
+            text: trimmed.to_string(),
+            selector: owner.filter(|el| el.id() != container.id()).and_then(|el| relative_selector(container, el)),
+        });
+    }
+    Some(CompositeValue { pieces, template: template.trim().to_string() })
+}
+
+// 記号だけの部品 (括弧や % など) は None を返し、テンプレートにそのまま埋め込む
+fn piece_kind(text: &str) -> Option<PieceKind> {
+    if matches!(text, "+" | "-" | "\u{2212}" | "±") {
+        Some(PieceKind::Sign)
+    } else if parse_decimal(text).is_some() || parse_percent(text).is_some() {
+        Some(PieceKind::Number)
+    } else if text.chars().all(|c| !c.is_alphanumeric()) {
+        None
+    } else {
+        Some(PieceKind::Text)
+    }
+}
+
+// container の子孫 element を指す "span._Foo_ > span:nth-of-type(2)" のようなセレクター。
+// container の中で最初に当たるのが element でなければ None
+fn relative_selector(container: ElementRef, element: ElementRef) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut current = element;
+    while current.id() != container.id() {
+        let name = current.value().name();
+        let token = current.value().classes().next().map(|c| class_token(c).0).unwrap_or_default();
+        let same_tag = current
+            .parent()
+            .into_iter()
+            .flat_map(|p| p.children().filter_map(ElementRef::wrap))
+            .filter(|sibling| sibling.value().name() == name)
+            .collect::<Vec<_>>();
+        if same_tag.len() > 1 {
+            let nth = same_tag.iter().position(|sibling| sibling.id() == current.id())? + 1;
+            parts.push(format!("{}{}:nth-of-type({})", name, token, nth));
+        } else {
+            parts.push(format!("{}{}", name, token));
+        }
+        current = current.parent_element()?;
+    }
+    parts.reverse();
+    let selector = parts.join(" > ");
+    let parsed = Selector::parse(&selector).ok()?;
+    (container.select(&parsed).next()?.id() == element.id()).then_some(selector)
+}
+
+// --- 複数ページに共通するセレクター ---
+// 銘柄ごとに生成したセレクターを、他のサンプルにも当てて何ページで対象を取れるかを数える。
+
+// 1ページ分のサンプル。target はそのページでの対象 (銘柄ごとに異なる)
+pub struct PageSample<'a> {
+    pub document: &'a Html,
+    pub target: &'a TextMatcher,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GeneralizedSelector {
+    pub selector: String,
+    pub strategy: SelectorStrategy,
+    pub score: u32,
+    pub stability: u32,
+    // 最初の一致が対象要素だったサンプル数
+    pub hits: usize,
+    // そのうち対象だけに一致したサンプル数
+    pub unique_hits: usize,
+    pub sample_count: usize,
+    // サンプルごとの一致数。対象に当たらなかったサンプルは null
+    pub match_counts: Vec<Option<usize>>,
+    pub reason: String,
+}
+
+impl GeneralizedSelector {
+    pub fn hits_every_sample(&self) -> bool {
+        self.hits == self.sample_count
+    }
+}
+
+/// 各サンプルで生成した候補を全サンプルに当て直し、対象を取れたページ数の多い順に返す。
+/// 対象のテキストが見つからないサンプルも分母に含める
+pub fn generalize_selectors(samples: &[PageSample]) -> Vec<GeneralizedSelector> {
+    let targets: Vec<_> = samples.iter().map(|s| find_target(s.document, s.target)).collect();
+
+    // 全サンプルの候補を合わせる。同じセレクターはスコアの高い方の情報を使う
+    let mut union: HashMap<String, SelectorCandidate> = HashMap::new();
+    for sample in samples {
+        for candidate in generate_selector_candidates_in(sample.document, sample.target) {
+            match union.get(&candidate.selector) {
+                Some(existing) if existing.score >= candidate.score => {}
+                _ => {
+                    union.insert(candidate.selector.clone(), candidate);
+                }
+            }
+        }
+    }
+
+    let mut generalized: Vec<_> = union
+        .into_values()
+        .map(|candidate| {
+            let match_counts: Vec<Option<usize>> = samples
+                .iter()
+                .zip(&targets)
+                .map(|(sample, target)| verify_selector(sample.document, &candidate.selector, (*target)?))
+                .collect();
+            let hits = match_counts.iter().flatten().count();
+            let unique_hits = match_counts.iter().flatten().filter(|&&n| n == 1).count();
+            GeneralizedSelector {
+                reason: format!("Hits {} of {} samples ({} unique)", hits, samples.len(), unique_hits),
+                selector: candidate.selector,
+                strategy: candidate.strategy,
+                score: candidate.score,
+                stability: candidate.stability,
+                hits,
+                unique_hits,
+                sample_count: samples.len(),
+                match_counts,
+            }
+        })
+        .collect();
+
+    generalized.sort_by(|a, b| {
+        b.hits
+            .cmp(&a.hits)
+            .then(b.unique_hits.cmp(&a.unique_hits))
+            .then((b.score * b.stability).cmp(&(a.score * a.stability)))
+            .then_with(|| a.selector.cmp(&b.selector))
+    });
+    generalized
+}
+
+// CSS Modules などが付けるビルドハッシュの接尾辞を除いた部分を返す。ハッシュは文字種ではなく位置で判定する
+// (4 文字のハッシュは数字を含まないことも多い)。Yahoo のページには次の2つの形がある:
+//   "Block__elem__<hash>" (hash は 4〜5 文字の base64)  "Rank__text__kiKw" => "Rank__text__"
+//   "_Block__elem_<hash>_<line>" (hash は 5 文字、line は CSS の行番号)  "_CommonPriceBoard__priceBlock_1g7gt_64" => "_CommonPriceBoard__priceBlock_"
+pub fn stable_class_prefix(name: &str) -> Option<&str> {
+    vite_module_prefix(name).or_else(|| bem_module_prefix(name))
+}
+
+// "_<local>_<hash:5>_<line>"
+fn vite_module_prefix(name: &str) -> Option<&str> {
+    let (local_and_hash, line) = name.strip_prefix('_')?.rsplit_once('_')?;
+    if line.is_empty() || !line.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (local, hash) = local_and_hash.rsplit_once('_')?;
+    let hashed = hash.len() == 5 && hash.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    if hashed && local.chars().any(|c| c.is_ascii_alphabetic()) {
+        Some(&name[..local.len() + 2])
+    } else {
+        None
     }
+}
 
-    // HashMapを(セレクター, スコア)のタプルのVecに変換
-    let mut sorted_candidates: Vec<_> = candidate_map.into_iter().collect();
-    
-    // スコアの降順でソート
-    sorted_candidates.sort_by(|a, b| b.1.cmp(&a.1));
+// "<Block>__<hash>" / "<Block>__<elem>__<hash>" / "<Block>--<mod>__<hash>"。ハッシュ自体に "_" や "-" が入ることもある
+fn bem_module_prefix(name: &str) -> Option<&str> {
+    for hash_len in [4, 5] {
+        let Some(split) = name.len().checked_sub(hash_len).filter(|&i| name.is_char_boundary(i)) else {
+            continue;
+        };
+        let (prefix, hash) = name.split_at(split);
+        let Some(base) = prefix.strip_suffix("__") else {
+            continue;
+        };
+        if !base.starts_with(|c: char| c.is_ascii_alphabetic()) || !hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            continue;
+        }
+        // "Card__item" のような素の BEM (Block__elem) と区別できないので、ブロック直後のハッシュは小文字だけのものを除く
+        let plain_bem = !base.contains("__") && !base.contains("--") && hash.chars().all(|c| c.is_ascii_lowercase());
+        if !plain_bem {
+            return Some(prefix);
+        }
+    }
+    None
+}
 
-    // セレクター文字列だけを抽出
-    sorted_candidates.into_iter().map(|(s, _)| s).collect()
+// クラス1つ分のセレクター断片と安定度。ハッシュ付きなら部分一致に置き換える
+fn class_token(class: &str) -> (String, u32) {
+    match stable_class_prefix(class) {
+        Some(prefix) => (format!("[class*='{}']", prefix), SUBSTRING),
+        None => (format!(".{}", class), STABLE),
+    }
 }
 
 // 候補をHashMapに追加/更新するヘルパー関数
-fn add_candidate(map: &mut HashMap<String, u32>, selector: String, score: u32) {
-    // 新しいセレクターを挿入するか、既存のセレクターのスコアをより高い方に更新
-    map.entry(selector)
-       .and_modify(|e| *e = (*e).max(score))
-       .or_insert(score);
+fn add_candidate(map: &mut HashMap<String, Generated>, selector: String, generated: Generated) {
+    // 新しいセレクターを挿入するか、既存のセレクターをスコアの高い方で置き換える
+    match map.get(&selector) {
+        Some(existing) if existing.score >= generated.score => {}
+        _ => {
+            map.insert(selector, generated);
+        }
+    }
+}
+
+fn candidate(score: u32, stability: u32, strategy: SelectorStrategy, reason: String) -> Generated {
+    Generated { score, stability, strategy, reason }
 }
 
 // 単一の要素からセレクターを生成し、HashMapに追加する
-fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, u32>) {
+fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, Generated>) {
     let tag_name = element.value().name();
 
     // 1. IDセレクター (最高スコア)
     if let Some(id) = element.value().id() {
         if !id.trim().is_empty() {
-            add_candidate(candidates, format!("#{}", id), 100);
+            let stability = if stable_class_prefix(id).is_some() { HASHED } else { STABLE };
+            add_candidate(candidates, format!("#{}", id), candidate(100, stability, SelectorStrategy::Id, format!("Element id '{}'", id)));
         }
     }
 
     // 2. Classセレクター
     let classes: Vec<_> = element.value().classes().filter(|c| !c.trim().is_empty()).collect();
     if !classes.is_empty() {
+        let any_hashed = classes.iter().any(|c| stable_class_prefix(c).is_some());
         let class_selector = classes.iter().map(|c| format!(".{}", c)).collect::<String>();
         // 全クラス結合 (例: tag.class1.class2)
-        add_candidate(candidates, format!("{}{}", tag_name, class_selector), 60);
+        let stability = if any_hashed { HASHED } else { STABLE };
+        add_candidate(
+            candidates,
+            format!("{}{}", tag_name, class_selector),
+            candidate(60, stability, SelectorStrategy::Class, "All classes of the element".to_string()),
+        );
+        // ハッシュ付きのクラスを部分一致に置き換えた全クラス結合
+        if any_hashed {
+            let stable_selector = classes.iter().map(|c| class_token(c).0).collect::<String>();
+            add_candidate(
+                candidates,
+                format!("{}{}", tag_name, stable_selector),
+                candidate(60, SUBSTRING, SelectorStrategy::ClassPrefix, "All classes without build hash suffixes".to_string()),
+            );
+        }
 
         for class in &classes {
             // 個別クラス (例: tag.class1)
-            add_candidate(candidates, format!("{}.{}", tag_name, class), 40);
+            match stable_class_prefix(class) {
+                Some(prefix) => {
+                    add_candidate(
+                        candidates,
+                        format!("{}.{}", tag_name, class),
+                        candidate(40, HASHED, SelectorStrategy::Class, format!("Single class '{}' (hashed, changes on redeploy)", class)),
+                    );
+                    add_candidate(
+                        candidates,
+                        format!("{}[class*='{}']", tag_name, prefix),
+                        candidate(45, SUBSTRING, SelectorStrategy::ClassPrefix, format!("Class '{}' without build hash suffix", class)),
+                    );
+                }
+                None => {
+                    add_candidate(
+                        candidates,
+                        format!("{}.{}", tag_name, class),
+                        candidate(40, STABLE, SelectorStrategy::Class, format!("Single class '{}'", class)),
+                    );
+                }
+            }
             // BEMライクなクラスの基底部分 (例: [class*="block__element"])
             if class.contains("__") {
                  if let Some(base) = class.split("__").next() {
                      if !base.is_empty() {
-                        add_candidate(candidates, format!("{}[class*='{}']", tag_name, base), 50);
+                        add_candidate(
+                            candidates,
+                            format!("{}[class*='{}']", tag_name, base),
+                            candidate(50, SUBSTRING, SelectorStrategy::BemPrefix, format!("BEM block '{}' of class '{}'", base, class)),
+                        );
                      }
                  }
             }
@@ -81,6 +693,9 @@ fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, u3
     }
 
     // 3. 親要素のコンテキストを利用したセレクター
+    // 同じタグの兄弟 (<span>(</span><span>-1.53</span><span>%</span> など) の中の対象にも届くよう、
+    // 対象自身の最初のクラスを付けた形も作る
+    let own_token = classes.first().map(|c| class_token(c));
     let mut current = Some(element);
     let mut path_parts = vec![tag_name.to_string()];
     let mut level = 1;
@@ -94,8 +709,14 @@ fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, u3
         if let Some(id) = parent.value().id() {
             let mut parent_path = path_parts.clone();
             parent_path.reverse();
-            let selector = format!("#{}{}", id, parent_path.join(" > "));
-            add_candidate(candidates, selector, 90 - level * 5); // 階層が浅いほど高スコア
+            let selector = format!("#{} > {}", id, parent_path.join(" > "));
+            let stability = if stable_class_prefix(id).is_some() { HASHED } else { STABLE };
+            // 階層が浅いほど高スコア
+            let generated = || candidate(90 - level * 5, stability, SelectorStrategy::ParentContext, format!("Ancestor id '{}' {} level(s) up", id, level));
+            if let Some((token, own_stability)) = &own_token {
+                add_candidate(candidates, format!("{}{}", selector, token), Generated { stability: stability.min(*own_stability), ..generated() });
+            }
+            add_candidate(candidates, selector, generated());
             break; // IDが見つかったらそこで打ち切り
         }
 
@@ -106,8 +727,13 @@ fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, u3
             if let Some(s_class) = specific_class {
                 let mut parent_path = path_parts.clone();
                 parent_path.reverse();
-                let selector = format!("{}.{} > {}", parent_tag, s_class, parent_path.join(" > "));
-                add_candidate(candidates, selector, 70 - level * 5);
+                let (token, stability) = class_token(s_class);
+                let selector = format!("{}{} > {}", parent_tag, token, parent_path.join(" > "));
+                let generated = || candidate(70 - level * 5, stability, SelectorStrategy::ParentContext, format!("Ancestor class '{}' {} level(s) up", s_class, level));
+                if let Some((own, own_stability)) = &own_token {
+                    add_candidate(candidates, format!("{}{}", selector, own), Generated { stability: stability.min(*own_stability), ..generated() });
+                }
+                add_candidate(candidates, selector, generated());
             }
         }
 
@@ -120,10 +746,303 @@ fn generate_for_element(element: ElementRef, candidates: &mut HashMap<String, u3
     for (attr, value) in element.value().attrs() {
         let lower_attr = attr.to_lowercase();
         if lower_attr != "class" && lower_attr != "id" && !value.trim().is_empty() {
-            add_candidate(candidates, format!("{}[{}='{}']", tag_name, attr, value), 30);
+            add_candidate(
+                candidates,
+                format!("{}[{}='{}']", tag_name, attr, value.replace('\'', "\\'")),
+                candidate(30, STABLE, SelectorStrategy::Attribute, format!("Attribute '{}'", attr)),
+            );
         }
     }
 
     // 5. タグ名のみ (最低スコア)
-    add_candidate(candidates, tag_name.to_string(), 1);
-}
\ No newline at end of file
+    add_candidate(candidates, tag_name.to_string(), candidate(1, STABLE, SelectorStrategy::Tag, "Tag name only".to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><body>
+        <div id="main">
+            <section><span class="label">価格</span><span class="value">2,862.5</span></section>
+            <section><span class="label">出来高</span><span class="value">1,234</span></section>
+        </div>
+    </body></html>"#;
+
+    #[test]
+    fn every_candidate_hits_the_target_first() {
+        let document = Html::parse_document(PAGE);
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("2,862.5"));
+        assert!(!candidates.is_empty());
+        for candidate in &candidates {
+            let selector = Selector::parse(&candidate.selector).unwrap();
+            let first = document.select(&selector).next().unwrap();
+            assert_eq!(first.text().collect::<String>(), "2,862.5", "{}", candidate.selector);
+            assert_eq!(document.select(&selector).count(), candidate.match_count);
+        }
+    }
+
+    #[test]
+    fn selectors_that_miss_the_target_are_dropped() {
+        let document = Html::parse_document(PAGE);
+        // "1,234" の span は2番目の span.value なので、span.value / span は最初に別の要素に当たる
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("1,234"));
+        assert!(!candidates.iter().any(|c| c.selector == "span.value" || c.selector == "span"));
+    }
+
+    #[test]
+    fn unique_selectors_rank_first() {
+        let document = Html::parse_document(r#"<div id="board"><p class="price">100</p></div><p class="price">200</p>"#);
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("100"));
+        // p.price は2要素に一致するので、親 ID 付きの一意なセレクターが先に来る
+        assert_eq!(candidates[0].selector, "#board > p");
+        assert_eq!(candidates[0].strategy, SelectorStrategy::ParentContext);
+        assert!(candidates[0].is_unique());
+        let class = candidates.iter().find(|c| c.selector == "p.price").unwrap();
+        assert_eq!(class.match_count, 2);
+        assert!(class.reason.contains("first of 2 matches"));
+    }
+
+    #[test]
+    fn target_among_same_tag_siblings_is_reachable() {
+        // "(" "-1.53" "%" ")" がすべて span で並ぶので、親のクラス > span だけでは "(" に当たる
+        let document = Html::parse_document(include_str!("../sample.html"));
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("-1.53"));
+        let best = candidates.first().expect("a verified selector");
+        assert_eq!(best.selector, "span[class*='PriceChangeLabel__secondary__'] > span[class*='StyledNumber__value__']");
+        assert_eq!(best.strategy, SelectorStrategy::ParentContext);
+    }
+
+    #[test]
+    fn parent_id_selector_has_a_combinator() {
+        let document = Html::parse_document(PAGE);
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("価格"));
+        assert!(candidates.iter().any(|c| c.selector == "#main > section > span"));
+    }
+
+    #[test]
+    fn unknown_text_has_no_candidates() {
+        assert!(generate_selector_candidates(PAGE, "存在しない").is_empty());
+    }
+
+    #[test]
+    fn hash_suffixes_are_detected() {
+        // DJI.html (ハッシュ + 行番号)
+        assert_eq!(stable_class_prefix("_CommonPriceBoard__priceBlock_1g7gt_64"), Some("_CommonPriceBoard__priceBlock_"));
+        assert_eq!(stable_class_prefix("_Ad_1y4vz_1"), Some("_Ad_"));
+        assert_eq!(stable_class_prefix("_JsDisabled_12030_1"), Some("_JsDisabled_"));
+        // sample.html (数字を含まない・"_" や "-" を含むハッシュ)
+        assert_eq!(stable_class_prefix("Rank__text__kiKw"), Some("Rank__text__"));
+        assert_eq!(stable_class_prefix("PriceBoardMenu__actions__nVbE"), Some("PriceBoardMenu__actions__"));
+        assert_eq!(stable_class_prefix("SupportLink__item__Inif"), Some("SupportLink__item__"));
+        assert_eq!(stable_class_prefix("DataListItem__date___6wH"), Some("DataListItem__date__"));
+        assert_eq!(stable_class_prefix("ServiceHeader__subNav__z__7"), Some("ServiceHeader__subNav__"));
+        assert_eq!(stable_class_prefix("ServiceFooter__-_lE"), Some("ServiceFooter__"));
+        assert_eq!(stable_class_prefix("Rank--simple__hNyU"), Some("Rank--simple__"));
+        assert_eq!(stable_class_prefix("PriceBoard__1zZr"), Some("PriceBoard__"));
+        assert_eq!(stable_class_prefix("style_ForeignIndexDetailContents__card__9fCh7"), Some("style_ForeignIndexDetailContents__card__"));
+        // ハッシュのないクラス
+        assert_eq!(stable_class_prefix("target_modules"), None);
+        assert_eq!(stable_class_prefix("PriceBoard__price"), None);
+        assert_eq!(stable_class_prefix("Card__item"), None);
+        assert_eq!(stable_class_prefix("col-12"), None);
+        assert_eq!(stable_class_prefix("value"), None);
+        assert_eq!(stable_class_prefix("_1g7gt_64"), None);
+    }
+
+    #[test]
+    fn every_module_class_in_the_fixtures_is_hashed() {
+        let class = Selector::parse("[class]").unwrap();
+        for html in [include_str!("../sample.html"), include_str!("../DJI.html")] {
+            let document = Html::parse_document(html);
+            for name in document.select(&class).flat_map(|el| el.value().classes()) {
+                // target_modules 以外はすべて CSS Modules のクラス
+                assert_eq!(stable_class_prefix(name).is_some(), name != "target_modules", "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn hashed_classes_prefer_stable_prefix_selectors() {
+        let document = Html::parse_document(
+            r#"<div class="_CommonPriceBoard__priceBlock_1g7gt_64"><span class="_StyledNumber__value_9o0uf_9">2,862.5</span></div>
+               <div class="_CommonPriceBoard__mainFooter_1g7gt_48"><span class="_StyledNumber__suffix_9o0uf_9">円</span></div>"#,
+        );
+        let candidates = generate_selector_candidates_in(&document, &TextMatcher::exact("2,862.5"));
+        let position = |selector: &str| candidates.iter().position(|c| c.selector == selector).unwrap();
+        // 上位はハッシュを含まないセレクター
+        assert_eq!(candidates[0].selector, "div[class*='_CommonPriceBoard__priceBlock_'] > span");
+        assert!(candidates[0].stability >= SUBSTRING);
+        let prefix = &candidates[position("span[class*='_StyledNumber__value_']")];
+        let exact = &candidates[position("span._StyledNumber__value_9o0uf_9")];
+        assert_eq!(prefix.strategy, SelectorStrategy::ClassPrefix);
+        assert!(exact.stability < prefix.stability);
+        assert!(position(&prefix.selector) < position(&exact.selector));
+    }
+
+    #[test]
+    fn generalized_selectors_hit_every_sample_first() {
+        let toyota = Html::parse_document(r#"<div id="title"><h2>トヨタ自動車(株)</h2></div><section class="board"><span class="price">2,862.5</span></section>"#);
+        let sony = Html::parse_document(r#"<div id="title"><h2>ソニーグループ(株)</h2></div><span class="badge">注目</span><section class="board"><span class="price">3,100</span></section>"#);
+        let samples = [
+            PageSample { document: &toyota, target: &TextMatcher::exact("2,862.5") },
+            PageSample { document: &sony, target: &TextMatcher::exact("3,100") },
+        ];
+        let generalized = generalize_selectors(&samples);
+        let top = &generalized[0];
+        assert!(top.hits_every_sample());
+        assert_eq!(top.match_counts, vec![Some(1), Some(1)]);
+        // "span" はトヨタのページでは対象に当たるが、ソニーのページでは先にバッジに当たる
+        let tag = generalized.iter().find(|g| g.selector == "span").unwrap();
+        assert_eq!(tag.match_counts, vec![Some(1), None]);
+        assert!(!tag.hits_every_sample());
+    }
+
+    #[test]
+    fn samples_without_the_target_count_as_misses() {
+        let page = Html::parse_document(r#"<p class="price">100</p>"#);
+        let samples = [
+            PageSample { document: &page, target: &TextMatcher::exact("100") },
+            PageSample { document: &page, target: &TextMatcher::exact("999") },
+        ];
+        let generalized = generalize_selectors(&samples);
+        assert!(!generalized.is_empty());
+        assert!(generalized.iter().all(|g| g.hits == 1 && g.sample_count == 2));
+    }
+
+    const REPEATED: &str = r#"<html><body>
+        <header><span class="ticker">2,862.5</span></header>
+        <main><div class="board"><span>現在値</span><span class="price">2,862.5</span></div></main>
+        <table><tr><th>終値</th><td>2,862.5 円</td></tr></table>
+    </body></html>"#;
+
+    #[test]
+    fn every_matching_element_becomes_a_group() {
+        let document = Html::parse_document(REPEATED);
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::All);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups.iter().map(|g| g.region.as_deref()).collect::<Vec<_>>(), vec![Some("header"), Some("main"), Some("table")]);
+        assert_eq!(groups[1].dom_path, "html > body > main > div > span:nth-of-type(2)");
+        assert_eq!(groups[1].context, "現在値 2,862.5");
+        assert!(groups[0].exact && !groups[2].exact);
+        assert!(groups.iter().all(|g| !g.candidates.is_empty()));
+        assert_eq!(groups[1].candidates[0].match_count, 1);
+    }
+
+    #[test]
+    fn groups_can_be_chosen_by_index_region_or_container() {
+        let document = Html::parse_document(REPEATED);
+        let by_index = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::Index(2));
+        assert_eq!(by_index.len(), 1);
+        assert_eq!(by_index[0].index, 2);
+
+        let by_region = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::Region("main".into()));
+        assert_eq!(by_region.len(), 1);
+        assert_eq!(by_region[0].index, 1);
+
+        let within = TargetFilter::Within(Selector::parse("div.board").unwrap());
+        let scoped = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &within);
+        assert_eq!(scoped.len(), 1);
+        assert_eq!(scoped[0].index, 1);
+    }
+
+    #[test]
+    fn canonical_values_locate_rendered_text() {
+        use crate::text_match::MatchMode;
+        let document = Html::parse_document(r#"<div><span class="price">１,234.50</span><span class="unit">円</span></div>"#);
+        assert!(generate_selector_candidates_in(&document, &TextMatcher::exact("1234.5")).is_empty());
+        let numeric = TextMatcher::new(MatchMode::Numeric, "1234.5").unwrap();
+        let candidates = generate_selector_candidates_in(&document, &numeric);
+        assert_eq!(candidates[0].selector, "span.price");
+    }
+
+    #[test]
+    fn split_values_are_described_from_their_container() {
+        let document = Html::parse_document(
+            r#"<div class="_PriceChangeLabel__primary_hse06_56"><span>前日比</span>
+               <span class="_StyledNumber__punctuation_9o0uf_9">+</span><span class="_StyledNumber__value_9o0uf_9">12.3</span>(<span class="_StyledNumber__punctuation_9o0uf_9">+</span><span class="_StyledNumber__value_9o0uf_9">1.02</span>%)</div>"#,
+        );
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("+12.3(+1.02%)"), &TargetFilter::All);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].candidates.iter().any(|c| c.selector == "div[class*='_PriceChangeLabel__primary_']"));
+
+        let composite = groups[0].composite.as_ref().unwrap();
+        assert_eq!(composite.template, "{0}{1}({2}{3}%)");
+        let kinds: Vec<_> = composite.pieces.iter().map(|p| p.kind).collect();
+        assert_eq!(kinds, vec![PieceKind::Sign, PieceKind::Number, PieceKind::Sign, PieceKind::Number]);
+        assert_eq!(composite.pieces[3].text, "1.02");
+        assert_eq!(composite.pieces[3].selector.as_deref(), Some("span[class*='_StyledNumber__value_']:nth-of-type(5)"));
+
+        // セレクターで部品を取り出してテンプレートに当てはめると元の値に戻る
+        let container = document.select(&Selector::parse("div").unwrap()).next().unwrap();
+        let mut rebuilt = composite.template.clone();
+        for (i, piece) in composite.pieces.iter().enumerate() {
+            let sel = Selector::parse(piece.selector.as_ref().unwrap()).unwrap();
+            let text = container.select(&sel).next().unwrap().text().collect::<String>();
+            rebuilt = rebuilt.replace(&format!("{{{}}}", i), &text);
+        }
+        assert_eq!(rebuilt, "+12.3(+1.02%)");
+    }
+
+    #[test]
+    fn single_node_values_are_not_composite() {
+        let document = Html::parse_document(r#"<p><span class="price">2,862.5</span><span>円</span></p>"#);
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::All);
+        assert_eq!(groups[0].composite, None);
+    }
+
+    #[test]
+    fn groups_include_label_rules() {
+        let document = Html::parse_document(
+            r#"<dl><dt><span>始値</span></dt><dd><span class="_StyledNumber__value_9o0uf_9">2,850</span></dd></dl>"#,
+        );
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("2,850"), &TargetFilter::All);
+        assert_eq!(groups[0].label_rules.len(), 1);
+        assert_eq!(groups[0].label_rules[0].label, "始値");
+    }
+
+    #[test]
+    fn embedded_state_paths_are_ranked_with_css_candidates() {
+        let document = Html::parse_document(
+            r#"<html><head><script>window.__PRELOADED_STATE__ = {"priceBoard":{"price":"2,862.5","history":[2862.5]}};</script></head>
+               <body><span class="_StyledNumber__value_9o0uf_9">2,862.5</span></body></html>"#,
+        );
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::All);
+        // <script> 自体はグループにならない
+        assert_eq!(groups.len(), 1);
+        let best = &groups[0].candidates[0];
+        assert_eq!(best.selector, "json:__PRELOADED_STATE__.priceBoard.price");
+        assert_eq!(best.strategy, SelectorStrategy::JsonPath);
+        // 数値の JSON も一致するが、配列の添字を含むので安定度が低い
+        let indexed = groups[0].candidates.iter().find(|c| c.selector == "json:__PRELOADED_STATE__.priceBoard.history[0]").unwrap();
+        assert!(indexed.stability < best.stability);
+        // DOM の候補も同じリストに残る
+        assert!(groups[0].candidates.iter().any(|c| c.strategy == SelectorStrategy::ClassPrefix));
+    }
+
+    #[test]
+    fn values_only_in_embedded_state_get_a_json_group() {
+        // 描画前のページ: 株価ボードは "---" のままで、値は __PRELOADED_STATE__ にしかない
+        let document = Html::parse_document(
+            r#"<html><head><script>window.__PRELOADED_STATE__ = {"mainStocksPriceBoard":{"name":"トヨタ自動車(株)","price":"2,862.5"}};</script></head>
+               <body><div class="PriceBoard__main__1liM"><span class="_StyledNumber__value_9o0uf_9">---</span></div></body></html>"#,
+        );
+        let groups = generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::All);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dom_path, "");
+        assert_eq!(groups[0].candidates[0].selector, "json:__PRELOADED_STATE__.mainStocksPriceBoard.price");
+        assert!(groups[0].candidates.iter().all(|c| c.strategy == SelectorStrategy::JsonPath));
+        assert_eq!(generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::Index(0)).len(), 1);
+        assert!(generate_selector_groups_in(&document, &TextMatcher::exact("2,862.5"), &TargetFilter::Region("main".to_string())).is_empty());
+        // どこにもない値は空のまま
+        assert!(generate_selector_groups_in(&document, &TextMatcher::exact("9,999"), &TargetFilter::All).is_empty());
+    }
+
+    #[test]
+    fn json_paths_in_the_us_stock_fixture() {
+        let document = Html::parse_document(include_str!("../sample.html"));
+        let candidates = json_path_candidates(&ScriptStates::from_document(&document), &TextMatcher::exact("27.75"));
+        assert!(candidates.iter().any(|c| c.selector == "json:__PRELOADED_STATE__.mainUsStocksPriceBoard.price"));
+        assert!(candidates.len() <= MAX_JSON_CANDIDATES);
+    }
+}
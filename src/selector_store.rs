@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use worker::{kv::KvStore, *};
+
+use crate::PageType;
+
+// wrangler.toml の [[kv_namespaces]] で定義しているバインディング名
+pub const KV_BINDING: &str = "FIN_SELECTORS";
+
+const KEY_PREFIX: &str = "selectors:";
+// 取得元ごとに名前空間を分ける前のキー (selectors:{page_type}) は Yahoo!ファイナンスのもの
+const LEGACY_SOURCE: &str = "yahoo_jp";
+
+// これが1つでも欠けたセットでは /quote の値を組み立てられない (update_time は任意)
+pub const REQUIRED_FIELDS: [&str; 4] = ["name", "price", "change_abs", "change_pct"];
+
+// --- 学習済みセレクターの保存形式 ---
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelectorSet {
+    pub page_type: PageType,
+    // フィールド名 (name, price, change_abs, change_pct, update_time) => セレクター
+    pub fields: BTreeMap<String, String>,
+    pub updated_at: String,
+    // 管理APIでピン留めされたセットは自動学習で上書きしない
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl SelectorSet {
+    pub fn new(page_type: PageType, fields: BTreeMap<String, String>) -> Self {
+        SelectorSet {
+            page_type,
+            fields,
+            updated_at: chrono::Utc::now().to_rfc3339(),
+            pinned: false,
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields.get(field).map(|s| s.as_str())
+    }
+}
+
+/// fields にない (または空の) 必須フィールド名
+pub fn missing_required_fields(fields: &BTreeMap<String, String>) -> Vec<&'static str> {
+    REQUIRED_FIELDS.into_iter().filter(|f| fields.get(*f).is_none_or(|sel| sel.trim().is_empty())).collect()
+}
+
+/// selectors:{source}:{page_type} (source は QuoteSource::name)
+pub fn storage_key(source: &str, page_type: PageType) -> String {
+    format!("{}{}:{}", KEY_PREFIX, source, page_type.as_str())
+}
+
+fn legacy_storage_key(page_type: PageType) -> String {
+    format!("{}{}", KEY_PREFIX, page_type.as_str())
+}
+
+/// FIN_SELECTORS KV への読み書きをまとめたラッパー。取得元ごとにキーを分ける
+#[derive(Clone)]
+pub struct SelectorStore {
+    kv: KvStore,
+    source: &'static str,
+}
+
+impl SelectorStore {
+    pub fn new(kv: KvStore, source: &'static str) -> Self {
+        SelectorStore { kv, source }
+    }
+
+    // 名前空間なしの古いキーも読む対象か
+    fn reads_legacy_key(&self) -> bool {
+        self.source == LEGACY_SOURCE
+    }
+
+    /// load 1回で KV を読む最大回数 (サブリクエストの予算を確保するのに使う)
+    pub fn max_load_reads(&self) -> usize {
+        if self.reads_legacy_key() { 2 } else { 1 }
+    }
+
+    // KV の読み込みに失敗してもスクレイピング自体は継続させたいので、エラーはログに残して None を返す
+    pub async fn load(&self, page_type: PageType) -> Option<SelectorSet> {
+        if let Some(set) = self.load_key(&storage_key(self.source, page_type)).await {
+            return Some(set);
+        }
+        if self.reads_legacy_key() {
+            return self.load_key(&legacy_storage_key(page_type)).await;
+        }
+        None
+    }
+
+    async fn load_key(&self, key: &str) -> Option<SelectorSet> {
+        match self.kv.get(key).json::<SelectorSet>().await {
+            Ok(set) => set,
+            Err(e) => {
+                debug_log!("[SelectorStore] Failed to load {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    pub async fn save(&self, set: &SelectorSet) -> Result<()> {
+        let key = storage_key(self.source, set.page_type);
+        self.kv.put(&key, set)?.execute().await?;
+        debug_log!("[SelectorStore] Saved {} ({} fields)", key, set.fields.len());
+        Ok(())
+    }
+
+    // 古いキーが残っていると load で読み戻されてしまうので一緒に消す
+    pub async fn delete(&self, page_type: PageType) -> Result<()> {
+        self.kv.delete(&storage_key(self.source, page_type)).await?;
+        if self.reads_legacy_key() {
+            self.kv.delete(&legacy_storage_key(page_type)).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Vec<SelectorSet> {
+        let mut sets = Vec::new();
+        for page_type in PageType::ALL {
+            if let Some(set) = self.load(page_type).await {
+                sets.push(set);
+            }
+        }
+        sets
+    }
+
+    // 成功したスクレイピングで使ったセレクターを記録する。existing は呼び出し側で読み込み済みの現在のセット。
+    // 既存の内容と同じなら KV の書き込み回数を節約するため何もしない。
+    pub async fn remember(&self, existing: Option<&SelectorSet>, page_type: PageType, used_selectors: &HashMap<String, String>) {
+        if !should_remember(existing, used_selectors) {
+            return;
+        }
+        if let Err(e) = self.save(&SelectorSet::new(page_type, to_fields(used_selectors))).await {
+            debug_log!("[SelectorStore] Failed to save selectors for {}: {}", page_type.as_str(), e);
+        }
+    }
+}
+
+fn to_fields(used_selectors: &HashMap<String, String>) -> BTreeMap<String, String> {
+    used_selectors.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+/// used_selectors を学習させる (KV に書き込む) 必要があるか。空・ピン留め中・既存と同じ内容なら不要
+pub fn should_remember(existing: Option<&SelectorSet>, used_selectors: &HashMap<String, String>) -> bool {
+    !used_selectors.is_empty() && !existing.is_some_and(|set| set.pinned || set.fields == to_fields(used_selectors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_namespaced_by_source() {
+        assert_eq!(storage_key("yahoo_jp", PageType::PriceBoard), "selectors:yahoo_jp:priceboard");
+        assert_eq!(legacy_storage_key(PageType::Stock), "selectors:stock");
+    }
+
+    #[test]
+    fn required_fields_must_be_present_and_non_empty() {
+        let mut fields: BTreeMap<String, String> =
+            [("name", "h2"), ("price", "span.price"), ("update_time", "time")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        fields.insert("change_abs".to_string(), " ".to_string());
+        assert_eq!(missing_required_fields(&fields), vec!["change_abs", "change_pct"]);
+        fields.insert("change_abs".to_string(), "span.abs".to_string());
+        fields.insert("change_pct".to_string(), "span.pct".to_string());
+        assert!(missing_required_fields(&fields).is_empty());
+    }
+}
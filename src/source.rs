@@ -0,0 +1,118 @@
+use scraper::Html;
+use std::collections::HashMap;
+use std::future::Future;
+use worker::*;
+
+use crate::profile::{self, ExtractionProfile};
+use crate::{PageType, StockData};
+
+// --- 相場ページの取得元 ---
+// コード → URL の対応、ページ種別ごとの抽出プロファイル、ページの取得と解析をまとめたもの。
+// 別の公開相場ページやテスト用のローカル HTML を追加するときは、このトレイトを実装する。
+
+pub trait QuoteSource {
+    fn name(&self) -> &'static str;
+
+    fn quote_url(&self, code: &str) -> String;
+
+    fn page_type(&self, code: &str) -> PageType {
+        PageType::from_code(code)
+    }
+
+    fn profile(&self, page_type: PageType) -> Result<ExtractionProfile> {
+        ExtractionProfile::builtin(page_type)
+    }
+
+    fn fetch_html(&self, code: &str) -> impl Future<Output = Result<String>> {
+        let url = self.quote_url(code);
+        async move { fetch_text(&url).await }
+    }
+
+    // ページ種別に合った抽出プロファイルで解析する
+    fn parse(&self, code: &str, document: &Html) -> Result<StockData> {
+        let profile = self.profile(self.page_type(code))?;
+        Ok(profile::extract(document, &profile)?.into_stock_data(code))
+    }
+}
+
+pub async fn fetch_text(url: &str) -> Result<String> {
+    let mut res = Fetch::Url(Url::parse(url)?).send().await?;
+    res.text().await
+}
+
+// ルーティングから使う取得元。新しい取得元に切り替えるときはここだけ変える
+pub fn default_source() -> YahooFinanceJapan {
+    YahooFinanceJapan
+}
+
+// --- Yahoo!ファイナンス (finance.yahoo.co.jp) ---
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YahooFinanceJapan;
+
+impl QuoteSource for YahooFinanceJapan {
+    fn name(&self) -> &'static str {
+        "yahoo_jp"
+    }
+
+    fn quote_url(&self, code: &str) -> String {
+        format!("https://finance.yahoo.co.jp/quote/{}", code)
+    }
+}
+
+// --- 保存済み HTML を返す取得元 (sample.html などでのテスト用) ---
+// URL と抽出プロファイルは Yahoo と同じものを使い、取得だけをローカルに差し替える
+#[derive(Debug, Clone, Default)]
+pub struct FixtureSource {
+    pages: HashMap<String, String>,
+}
+
+impl FixtureSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_page(mut self, code: &str, html: impl Into<String>) -> Self {
+        self.pages.insert(code.to_string(), html.into());
+        self
+    }
+}
+
+impl QuoteSource for FixtureSource {
+    fn name(&self) -> &'static str {
+        "fixture"
+    }
+
+    fn quote_url(&self, code: &str) -> String {
+        YahooFinanceJapan.quote_url(code)
+    }
+
+    fn fetch_html(&self, code: &str) -> impl Future<Output = Result<String>> {
+        let page = self
+            .pages
+            .get(code)
+            .cloned()
+            .ok_or_else(|| Error::from(format!("No fixture page for '{}'", code)));
+        async move { page }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn yahoo_builds_quote_urls() {
+        assert_eq!(YahooFinanceJapan.quote_url("7203.T"), "https://finance.yahoo.co.jp/quote/7203.T");
+        assert_eq!(YahooFinanceJapan.quote_url("^DJI"), "https://finance.yahoo.co.jp/quote/^DJI");
+        assert_eq!(YahooFinanceJapan.page_type("USDJPY=X"), PageType::PriceBoard);
+    }
+
+    #[test]
+    fn fixture_source_serves_registered_pages_only() {
+        let source = FixtureSource::new().with_page("7203.T", "<html><h1>トヨタ</h1></html>");
+        assert_eq!(block_on(source.fetch_html("7203.T")).unwrap(), "<html><h1>トヨタ</h1></html>");
+        assert!(block_on(source.fetch_html("6758.T")).is_err());
+        assert_eq!(source.quote_url("7203.T"), YahooFinanceJapan.quote_url("7203.T"));
+    }
+}
@@ -0,0 +1,215 @@
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use worker::*;
+
+use crate::profile::{self, ExtractionProfile, FieldDiagnostic, FieldRule};
+use crate::scoring::ScoringConfig;
+use crate::source::{self, QuoteSource};
+use crate::{api_cors, scrape_dynamically_from_document, PageType, StockData};
+
+// --- POST /api/test-parser ---
+// 手元の HTML (sample.html など) に対して、Yahoo にアクセスせずに解析ロジックだけを実行する。
+// 優先順位: profile > selectors > page_type > code から判定したページ種別
+
+#[derive(Deserialize, Debug)]
+struct TestParserRequest {
+    html_content: String,
+    #[serde(default)]
+    code: String,
+    // "stock" | "priceboard" | "index" | "dynamic"
+    #[serde(default)]
+    page_type: Option<String>,
+    #[serde(default)]
+    profile: Option<ExtractionProfile>,
+    // フィールド名 => セレクター (KV の SelectorSet と同じ形)
+    #[serde(default)]
+    selectors: BTreeMap<String, String>,
+    // "dynamic" のときの候補の採点の重み。省略時は DISCOVERY_SCORING (なければ profiles/scoring.json)
+    #[serde(default)]
+    scoring: Option<ScoringConfig>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestParserResponse {
+    mode: String,
+    data: Option<StockData>,
+    error: Option<String>,
+    diagnostics: Vec<FieldDiagnostic>,
+}
+
+pub async fn preflight(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    Response::empty()?.with_cors(&api_cors())
+}
+
+pub async fn handle_test_parser(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: TestParserRequest = match req.json().await {
+        Ok(b) => b,
+        Err(e) => return Response::error(format!("Invalid request body: {}", e), 400)?.with_cors(&api_cors()),
+    };
+    if body.html_content.trim().is_empty() {
+        return Response::error("'html_content' is required", 400)?.with_cors(&api_cors());
+    }
+
+    let result = run(body, &ctx);
+    Response::from_json(&result)?.with_cors(&api_cors())
+}
+
+fn run(body: TestParserRequest, ctx: &RouteContext<()>) -> TestParserResponse {
+    let code = body.code.trim().to_string();
+
+    let (mode, profile) = if let Some(profile) = body.profile {
+        (format!("profile:{}", profile.name), profile)
+    } else if !body.selectors.is_empty() {
+        return run_selectors(&body.html_content, &code, &body.selectors);
+    } else if body.page_type.as_deref() == Some("dynamic") {
+        let scoring = body.scoring.unwrap_or_else(|| ScoringConfig::from_ctx(ctx));
+        return run_dynamic(&code, &body.html_content, &scoring);
+    } else {
+        let page_type = match body.page_type.as_deref() {
+            Some(name) => match PageType::from_name(name) {
+                Some(t) => t,
+                None => return failed(format!("page_type:{}", name), format!("Unknown page_type '{}'", name)),
+            },
+            None => PageType::from_code(&code),
+        };
+        match ExtractionProfile::builtin(page_type) {
+            Ok(p) => (format!("profile:{}", p.name), p),
+            Err(e) => return failed(page_type.as_str().to_string(), e.to_string()),
+        }
+    };
+
+    run_profile(&body.html_content, &code, mode, &profile)
+}
+
+/// フィールド名 => セレクターを HTML に当てる (KV に保存する前の確認用)。構文エラーのセレクターは status "invalid_selector"
+pub fn run_selectors(html: &str, code: &str, selectors: &BTreeMap<String, String>) -> TestParserResponse {
+    let mut response = run_profile(html, code, "selectors".to_string(), &profile_from_selectors(selectors));
+    for diagnostic in &mut response.diagnostics {
+        let invalid = selectors.get(&diagnostic.field).is_some_and(|sel| Selector::parse(sel).is_err());
+        if invalid {
+            diagnostic.status = "invalid_selector";
+        }
+    }
+    response
+}
+
+fn run_profile(html: &str, code: &str, mode: String, profile: &ExtractionProfile) -> TestParserResponse {
+    let document = Html::parse_document(html);
+    match profile::extract(&document, profile) {
+        Ok(extraction) => {
+            let diagnostics = extraction.diagnostics.clone();
+            let fallback_code = if code.is_empty() { "N/A" } else { code };
+            TestParserResponse { mode, data: Some(extraction.into_stock_data(fallback_code)), error: None, diagnostics }
+        }
+        Err(e) => failed(mode, e.to_string()),
+    }
+}
+
+// セレクターを1つずつ指定された場合は、ページ全体を対象にした単純なプロファイルとして実行する
+fn profile_from_selectors(selectors: &BTreeMap<String, String>) -> ExtractionProfile {
+    ExtractionProfile {
+        name: "selectors".to_string(),
+        containers: Vec::new(),
+        fields: selectors
+            .iter()
+            .map(|(field, selector)| FieldRule {
+                field: field.clone(),
+                selectors: vec![selector.clone()],
+                json: Vec::new(),
+                labels: Vec::new(),
+                transforms: Vec::new(),
+                required: false,
+                default: None,
+            })
+            .collect(),
+    }
+}
+
+fn run_dynamic(code: &str, html: &str, scoring: &ScoringConfig) -> TestParserResponse {
+    let source = source::default_source();
+    let document = Html::parse_document(html);
+    match scrape_dynamically_from_document(code, source.page_type(code), source.quote_url(code), &document, scoring) {
+        Ok(result) => {
+            let values = [
+                ("name", &result.data.name),
+                ("price", &result.data.price),
+                ("change_abs", &result.data.change_abs),
+                ("change_pct", &result.data.change_pct),
+                ("update_time", &result.data.update_time),
+            ];
+            let diagnostics = values
+                .iter()
+                .map(|(field, value)| {
+                    let selector = result.used_selectors.get(*field).cloned();
+                    let status = if value.is_empty() || selector.is_none() { "missing" } else { "found" };
+                    FieldDiagnostic {
+                        field: field.to_string(),
+                        status,
+                        selector,
+                        raw_text: None,
+                        value: Some(value.to_string()),
+                    }
+                })
+                .collect();
+            TestParserResponse { mode: "dynamic".to_string(), data: Some(result.data), error: None, diagnostics }
+        }
+        Err(e) => failed("dynamic".to_string(), e.to_string()),
+    }
+}
+
+fn failed(mode: String, error: String) -> TestParserResponse {
+    TestParserResponse { mode, data: None, error: Some(error), diagnostics: Vec::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = include_str!("../sample.html");
+
+    fn selectors(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(field, sel)| (field.to_string(), sel.to_string())).collect()
+    }
+
+    fn diagnostic<'a>(response: &'a TestParserResponse, field: &str) -> &'a FieldDiagnostic {
+        response.diagnostics.iter().find(|d| d.field == field).unwrap()
+    }
+
+    #[test]
+    fn selectors_on_us_stock_fixture() {
+        let response = run_selectors(
+            SAMPLE,
+            "SONY",
+            &selectors(&[
+                ("name", "div[class*='PriceBoard__main'] header h2"),
+                ("price", "span[class*='PriceBoard__price'] span[class*='StyledNumber__value']"),
+                ("change_abs", "span[class*='PriceChangeLabel__primary'] span[class*='StyledNumber__value']"),
+                ("update_time", "ul[class*='PriceBoard__times'] time"),
+            ]),
+        );
+        assert_eq!(response.mode, "selectors");
+        assert_eq!(response.error, None);
+        let data = response.data.as_ref().unwrap();
+        assert_eq!((data.name.as_str(), data.code.as_str(), data.price.as_str()), ("ソニーグループ(株)", "SONY", "27.75"));
+        assert_eq!(data.change_abs, "-0.43");
+        assert!(response.diagnostics.iter().all(|d| d.status == "found"));
+        assert_eq!(diagnostic(&response, "update_time").value.as_deref(), Some("10/31 9:04"));
+    }
+
+    #[test]
+    fn broken_and_unmatched_selectors_are_reported() {
+        let response = run_selectors(
+            SAMPLE,
+            "SONY",
+            &selectors(&[("name", "header h2"), ("price", "span[[class*='PriceBoard__price']"), ("change_pct", "span.no-such-class")]),
+        );
+        assert_eq!(response.error, None);
+        assert_eq!(diagnostic(&response, "name").status, "found");
+        let price = diagnostic(&response, "price");
+        assert_eq!((price.status, price.selector.as_ref(), price.value.as_ref()), ("invalid_selector", None, None));
+        assert_eq!(diagnostic(&response, "change_pct").status, "missing");
+        // 取れなかったフィールドは空のまま
+        assert_eq!(response.data.as_ref().unwrap().price, "");
+    }
+}
@@ -0,0 +1,183 @@
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+use worker::*;
+
+use crate::parsing::parse_decimal;
+
+// --- 対象テキストの照合 ---
+// セレクター生成で「この要素が対象か」を判定する。表示どおりの文字列を渡せない場合
+// ("1234.5" を渡したいが表示は "1,234.50"、全角数字、span の間の空白など) はモードを切り替える。
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    // 表示どおりの部分一致 (従来の動作)
+    #[default]
+    Exact,
+    // 空白をすべて取り除いてから比較する (<span>2,862</span> <span>.5</span> など)
+    Whitespace,
+    // NFKC 正規化 (全角英数 → 半角など) に加えて空白も取り除く
+    Nfkc,
+    // テキスト中の数値のいずれかが対象の数値と等しい ("1234.5" と "1,234.50")
+    Numeric,
+    // 対象を正規表現として扱う
+    Regex,
+}
+
+impl MatchMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exact" => Some(MatchMode::Exact),
+            "whitespace" => Some(MatchMode::Whitespace),
+            "nfkc" => Some(MatchMode::Nfkc),
+            "numeric" => Some(MatchMode::Numeric),
+            "regex" => Some(MatchMode::Regex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextMatcher {
+    mode: MatchMode,
+    // モードに合わせて正規化済みの対象
+    target: String,
+    number: Option<Decimal>,
+    regex: Option<Regex>,
+}
+
+impl TextMatcher {
+    pub fn new(mode: MatchMode, target: &str) -> Result<Self> {
+        // 数値として読める対象は JSON の数値とも比較できるようにしておく
+        let number = parse_decimal(&nfkc(target));
+        let mut matcher = TextMatcher { mode, target: normalize(mode, target), number, regex: None };
+        match mode {
+            MatchMode::Numeric if matcher.number.is_none() => {
+                return Err(Error::from(format!("'{}' is not a number", target)));
+            }
+            MatchMode::Regex => {
+                let regex = Regex::new(target).map_err(|e| Error::from(format!("Invalid regex '{}': {}", target, e)))?;
+                matcher.regex = Some(regex);
+            }
+            _ => {}
+        }
+        Ok(matcher)
+    }
+
+    pub fn exact(target: &str) -> Self {
+        TextMatcher { mode: MatchMode::Exact, target: target.to_string(), number: parse_decimal(&nfkc(target)), regex: None }
+    }
+
+    pub fn mode(&self) -> MatchMode {
+        self.mode
+    }
+
+    /// text のどこかに対象が含まれるか
+    pub fn matches(&self, text: &str) -> bool {
+        match self.mode {
+            MatchMode::Numeric => numbers_in(text).into_iter().any(|n| Some(n) == self.number),
+            MatchMode::Regex => self.regex.as_ref().is_some_and(|re| re.is_match(text)),
+            mode => normalize(mode, text).contains(&self.target),
+        }
+    }
+
+    /// 数値が対象と等しいか (対象が数値として読めない場合は false)
+    pub fn matches_number(&self, number: Decimal) -> bool {
+        self.number == Some(number)
+    }
+
+    /// text 全体が対象と一致するか (前後の空白は無視)
+    pub fn matches_whole(&self, text: &str) -> bool {
+        let text = text.trim();
+        match self.mode {
+            MatchMode::Numeric => parse_decimal(&nfkc(text)).is_some_and(|n| Some(n) == self.number),
+            MatchMode::Regex => self
+                .regex
+                .as_ref()
+                .and_then(|re| re.find(text))
+                .is_some_and(|m| m.start() == 0 && m.end() == text.len()),
+            MatchMode::Exact => text == self.target.trim(),
+            mode => normalize(mode, text) == self.target,
+        }
+    }
+}
+
+fn nfkc(text: &str) -> String {
+    text.nfkc().collect()
+}
+
+fn normalize(mode: MatchMode, text: &str) -> String {
+    match mode {
+        MatchMode::Whitespace => text.chars().filter(|c| !c.is_whitespace()).collect(),
+        MatchMode::Nfkc => nfkc(text).chars().filter(|c| !c.is_whitespace()).collect(),
+        _ => text.to_string(),
+    }
+}
+
+fn number_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"[+\-\u{2212}]?\d[\d,]*(?:\.\d+)?").unwrap())
+}
+
+fn numbers_in(text: &str) -> Vec<Decimal> {
+    let text = nfkc(text);
+    number_regex().find_iter(&text).filter_map(|m| parse_decimal(m.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(mode: MatchMode, target: &str) -> TextMatcher {
+        TextMatcher::new(mode, target).unwrap()
+    }
+
+    #[test]
+    fn exact_is_a_plain_substring_match() {
+        let m = TextMatcher::exact("2,862.5");
+        assert!(m.matches("現在値 2,862.5 円"));
+        assert!(!m.matches("2,862 .5"));
+        assert!(m.matches_whole(" 2,862.5 "));
+    }
+
+    #[test]
+    fn whitespace_mode_ignores_spacing() {
+        let m = matcher(MatchMode::Whitespace, "トヨタ 自動車");
+        assert!(m.matches("トヨタ自動車(株)"));
+        assert!(matcher(MatchMode::Whitespace, "2,862.5").matches_whole("2,862 .5"));
+    }
+
+    #[test]
+    fn nfkc_mode_folds_full_width_characters() {
+        let m = matcher(MatchMode::Nfkc, "2,862.5");
+        assert!(m.matches("２，８６２．５"));
+        assert!(!matcher(MatchMode::Whitespace, "2,862.5").matches("２，８６２．５"));
+    }
+
+    #[test]
+    fn numeric_mode_compares_values() {
+        let m = matcher(MatchMode::Numeric, "1234.5");
+        assert!(m.matches("終値 1,234.50 円"));
+        assert!(m.matches("１，２３４．５"));
+        assert!(!m.matches("1,234.55"));
+        assert!(m.matches_whole("1,234.50"));
+        assert!(!m.matches_whole("1,234.50 円"));
+        assert!(matcher(MatchMode::Numeric, "-12").matches("前日比 −12"));
+        assert!(TextMatcher::new(MatchMode::Numeric, "N/A").is_err());
+        // 数値との比較はモードによらない
+        assert!(TextMatcher::exact("2,862.5").matches_number(Decimal::new(28625, 1)));
+        assert!(!TextMatcher::exact("N/A").matches_number(Decimal::ZERO));
+    }
+
+    #[test]
+    fn regex_mode() {
+        let m = matcher(MatchMode::Regex, r"^\d{1,2}:\d{2}$");
+        assert!(m.matches("15:00"));
+        assert!(m.matches_whole("15:00"));
+        assert!(!m.matches("2024/10/17"));
+        assert!(TextMatcher::new(MatchMode::Regex, "(").is_err());
+    }
+}
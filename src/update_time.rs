@@ -0,0 +1,361 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::parsing::to_half_width;
+
+// --- update_time の解決 ---
+// <time> 要素の表示は "15:00" (当日の時刻) や "10/17" (引け後は日付のみ) のように日付や年が欠けている。
+// 取引所のタイムゾーンと現在時刻から欠けた部分を補い、RFC 3339 のタイムスタンプにする。
+
+const fn hm(hour: u32, minute: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+}
+
+// ザラ場の時刻を判定するための取引所情報
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Market {
+    // ページに表示される時刻のタイムゾーン
+    pub tz: Tz,
+    // 取引時間帯 (sessions・week) を判定するタイムゾーン。為替は表示が日本時間でも取引はニューヨーク時間で区切る
+    pub session_tz: Tz,
+    // 日付だけが表示されている場合は、その日の終値の時刻とみなす
+    pub close: NaiveTime,
+    // 1日の取引時間帯 (session_tz の時刻、[開始, 終了))。祝日は考慮しない
+    pub sessions: &'static [(NaiveTime, NaiveTime)],
+    // 取引する曜日の範囲 (session_tz の曜日と時刻、[開始, 終了))。終了が開始より前なら週末をまたぐ
+    pub week: (Weekday, NaiveTime, Weekday, NaiveTime),
+}
+
+// 月曜 0:00 から土曜 0:00 まで
+const WEEKDAYS: (Weekday, NaiveTime, Weekday, NaiveTime) = (Weekday::Mon, NaiveTime::MIN, Weekday::Sat, NaiveTime::MIN);
+const ALL_DAY: &[(NaiveTime, NaiveTime)] = &[(NaiveTime::MIN, NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).unwrap())];
+
+impl Market {
+    // 平日だけ現地時刻で取引し、現地時刻で表示される取引所
+    const fn exchange(tz: Tz, close: NaiveTime, sessions: &'static [(NaiveTime, NaiveTime)]) -> Market {
+        Market { tz, session_tz: tz, close, sessions, week: WEEKDAYS }
+    }
+
+    pub const TOKYO: Market = Market::exchange(chrono_tz::Asia::Tokyo, hm(15, 30), &[(hm(9, 0), hm(11, 30)), (hm(12, 30), hm(15, 30))]);
+
+    pub const NEW_YORK: Market = Market::exchange(chrono_tz::America::New_York, hm(16, 0), &[(hm(9, 30), hm(16, 0))]);
+
+    // 米国株 (SONY・AAPL など) はニューヨークで取引されるが、ページには日本時間で表示される
+    pub const US_LISTED: Market = Market { tz: chrono_tz::Asia::Tokyo, ..Market::NEW_YORK };
+
+    // 為替はニューヨーク時間の日曜 17:00 (ウェリントンの週明け) から金曜 17:00 (ニューヨークの引け) まで切れ目なく動く
+    pub const FX: Market = Market {
+        tz: chrono_tz::Asia::Tokyo,
+        session_tz: chrono_tz::America::New_York,
+        close: hm(15, 30),
+        sessions: ALL_DAY,
+        week: (Weekday::Sun, hm(17, 0), Weekday::Fri, hm(17, 0)),
+    };
+
+    pub const LONDON: Market = Market::exchange(chrono_tz::Europe::London, hm(16, 30), &[(hm(8, 0), hm(16, 30))]);
+
+    pub const FRANKFURT: Market = Market::exchange(chrono_tz::Europe::Berlin, hm(17, 30), &[(hm(9, 0), hm(17, 30))]);
+
+    pub const PARIS: Market = Market::exchange(chrono_tz::Europe::Paris, hm(17, 30), &[(hm(9, 0), hm(17, 30))]);
+
+    pub const HONG_KONG: Market = Market::exchange(chrono_tz::Asia::Hong_Kong, hm(16, 0), &[(hm(9, 30), hm(12, 0)), (hm(13, 0), hm(16, 0))]);
+
+    pub const SHANGHAI: Market = Market::exchange(chrono_tz::Asia::Shanghai, hm(15, 0), &[(hm(9, 30), hm(11, 30)), (hm(13, 0), hm(15, 0))]);
+
+    pub const SEOUL: Market = Market::exchange(chrono_tz::Asia::Seoul, hm(15, 30), &[(hm(9, 0), hm(15, 30))]);
+
+    /// "^DJI" などの海外指数は現地時刻で表示される。それ以外 (国内株・大証・米国株・為替) は日本時間。
+    /// 国内のコードは数字で始まり ("7203", "7203.T", "130A.T", "998407.O")、米国株のティッカーは英字だけ ("SONY", "BRK.B")。
+    /// INDEX_MARKETS にない "^" コードやどちらの形でもないコードは取引所が分からないので None
+    pub fn for_code(code: &str) -> Option<Market> {
+        if code.starts_with('^') {
+            INDEX_MARKETS.iter().find(|(index, _)| index.eq_ignore_ascii_case(code)).map(|(_, market)| *market)
+        } else if code.ends_with("=X") {
+            Some(Market::FX)
+        } else if code.starts_with(|c: char| c.is_ascii_digit()) {
+            Some(Market::TOKYO)
+        } else if code.starts_with(|c: char| c.is_ascii_alphabetic()) && code.chars().all(|c| c.is_ascii_alphabetic() || c == '.' || c == '-') {
+            Some(Market::US_LISTED)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.session_tz).naive_local();
+        let time = local.time();
+        self.in_week(local) && self.sessions.iter().any(|(start, end)| *start <= time && time < *end)
+    }
+
+    /// now より後で最初に始まる取引時間帯の開始時刻 (取引のない曜日は飛ばす)
+    pub fn next_open(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = now.with_timezone(&self.session_tz).date_naive();
+        let (open_day, open_time, _, _) = self.week;
+        // 金曜の引け後でも翌週の週明けまでには見つかる
+        (0..8)
+            .filter_map(|days| today.checked_add_signed(Duration::days(days)))
+            .flat_map(|date| {
+                let week_open = (date.weekday() == open_day).then(|| date.and_time(open_time));
+                self.sessions.iter().map(move |(start, _)| date.and_time(*start)).chain(week_open)
+            })
+            .filter_map(|naive| self.session_tz.from_local_datetime(&naive).earliest())
+            .map(|dt| dt.with_timezone(&Utc))
+            .filter(|open| *open > now && self.is_open(*open))
+            .min()
+    }
+
+    // session_tz の日時が取引する曜日の範囲に入っているか
+    fn in_week(&self, local: NaiveDateTime) -> bool {
+        let (open_day, open_time, close_day, close_time) = self.week;
+        let position = week_position(local.weekday(), local.time());
+        let (open, close) = (week_position(open_day, open_time), week_position(close_day, close_time));
+        if open <= close {
+            open <= position && position < close
+        } else {
+            position >= open || position < close
+        }
+    }
+}
+
+// 月曜 0:00 からの経過秒数
+fn week_position(day: Weekday, time: NaiveTime) -> u32 {
+    day.num_days_from_monday() * 86_400 + time.num_seconds_from_midnight()
+}
+
+// Yahoo!ファイナンスの指数コード => 指数を算出している取引所
+const INDEX_MARKETS: &[(&str, Market)] = &[
+    ("^DJI", Market::NEW_YORK),
+    ("^GSPC", Market::NEW_YORK),
+    ("^IXIC", Market::NEW_YORK),
+    ("^NDX", Market::NEW_YORK),
+    ("^SOX", Market::NEW_YORK),
+    ("^RUT", Market::NEW_YORK),
+    ("^VIX", Market::NEW_YORK),
+    ("^N225", Market::TOKYO),
+    ("^FTSE", Market::LONDON),
+    ("^GDAXI", Market::FRANKFURT),
+    ("^FCHI", Market::PARIS),
+    ("^HSI", Market::HONG_KONG),
+    ("^SSEC", Market::SHANGHAI),
+    ("^KS11", Market::SEOUL),
+];
+
+// 表示時刻が現在より少し先になるのは時計のずれとみなし、前日扱いにしない
+const CLOCK_SKEW_MINUTES: i64 = 10;
+
+fn date_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?:(\d{4})[/\-年])?(\d{1,2})[/\-月](\d{1,2})日?").unwrap())
+}
+
+fn time_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(\d{1,2}):(\d{2})(?::(\d{2}))?").unwrap())
+}
+
+/// 表示文字列を取引所のタイムゾーン付きタイムスタンプにする。日付も時刻も読み取れなければ None
+pub fn resolve_update_time(raw: &str, market: &Market, now: DateTime<Utc>) -> Option<DateTime<FixedOffset>> {
+    let text = to_half_width(raw);
+    let local_now = now.with_timezone(&market.tz);
+    let today = local_now.date_naive();
+
+    let time = time_regex().captures(&text).and_then(|caps| {
+        let hour = caps[1].parse().ok()?;
+        let minute = caps[2].parse().ok()?;
+        let second = caps.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        NaiveTime::from_hms_opt(hour, minute, second)
+    });
+
+    let date = date_regex().captures(&text).and_then(|caps| {
+        let month: u32 = caps[2].parse().ok()?;
+        let day: u32 = caps[3].parse().ok()?;
+        match caps.get(1) {
+            Some(year) => NaiveDate::from_ymd_opt(year.as_str().parse().ok()?, month, day),
+            None => infer_year(today, month, day),
+        }
+    });
+
+    let naive = match (date, time) {
+        (Some(date), Some(time)) => date.and_time(time),
+        // 日付のみ: 引け後の表示なので終値の時刻
+        (Some(date), None) => date.and_time(market.close),
+        // 時刻のみ: 当日。ただし現在より先なら日付をまたいだ直後とみなして前日
+        (None, Some(time)) => {
+            let candidate = today.and_time(time);
+            if candidate > local_now.naive_local() + Duration::minutes(CLOCK_SKEW_MINUTES) {
+                candidate - Duration::days(1)
+            } else {
+                candidate
+            }
+        }
+        (None, None) => return None,
+    };
+    to_market_time(naive, market)
+}
+
+// 年が省略された日付は、今日より先にならない直近の年とみなす (1/2 に "12/30" が表示される場合など)
+fn infer_year(today: NaiveDate, month: u32, day: u32) -> Option<NaiveDate> {
+    let this_year = NaiveDate::from_ymd_opt(today.year(), month, day);
+    match this_year {
+        Some(date) if date <= today => Some(date),
+        _ => NaiveDate::from_ymd_opt(today.year() - 1, month, day),
+    }
+}
+
+fn to_market_time(naive: NaiveDateTime, market: &Market) -> Option<DateTime<FixedOffset>> {
+    // 夏時間の切り替えで存在しない/重複する時刻は早い方を採用する
+    market.tz.from_local_datetime(&naive).earliest().map(|dt| dt.fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn resolve(raw: &str, market: &Market, now: &str) -> Option<String> {
+        resolve_update_time(raw, market, utc(now)).map(|dt| dt.to_rfc3339())
+    }
+
+    #[test]
+    fn time_only_is_today_in_tokyo() {
+        // 2024-10-17 15:05 JST
+        assert_eq!(resolve("15:00", &Market::TOKYO, "2024-10-17T06:05:00Z").as_deref(), Some("2024-10-17T15:00:00+09:00"));
+        assert_eq!(resolve("09:01:30", &Market::TOKYO, "2024-10-17T06:05:00Z").as_deref(), Some("2024-10-17T09:01:30+09:00"));
+    }
+
+    #[test]
+    fn time_in_the_future_rolls_back_a_day() {
+        // 2024-10-18 00:05 JST に "23:59" が表示されている
+        assert_eq!(resolve("23:59", &Market::TOKYO, "2024-10-17T15:05:00Z").as_deref(), Some("2024-10-17T23:59:00+09:00"));
+        // 数分先の時刻は時計のずれとして当日扱い
+        assert_eq!(resolve("15:10", &Market::TOKYO, "2024-10-17T06:05:00Z").as_deref(), Some("2024-10-17T15:10:00+09:00"));
+    }
+
+    #[test]
+    fn date_only_uses_market_close() {
+        assert_eq!(resolve("10/17", &Market::TOKYO, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T15:30:00+09:00"));
+        assert_eq!(resolve("10/17", &Market::NEW_YORK, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T16:00:00-04:00"));
+    }
+
+    #[test]
+    fn year_is_inferred_across_new_year() {
+        assert_eq!(resolve("12/30", &Market::TOKYO, "2025-01-02T01:00:00Z").as_deref(), Some("2024-12-30T15:30:00+09:00"));
+    }
+
+    #[test]
+    fn full_dates_and_japanese_formats() {
+        assert_eq!(resolve("2024/10/17 15:00", &Market::TOKYO, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T15:00:00+09:00"));
+        assert_eq!(resolve("2024年10月17日", &Market::TOKYO, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T15:30:00+09:00"));
+        assert_eq!(resolve("１０/１７ １１:３０", &Market::TOKYO, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T11:30:00+09:00"));
+    }
+
+    #[test]
+    fn labels_around_the_time_are_ignored() {
+        // Untitled-1.rs の format_update_time が扱っていた "（リアルタイム：10:15）" 形式
+        assert_eq!(resolve("（リアルタイム：10:15）", &Market::TOKYO, "2024-10-17T02:00:00Z").as_deref(), Some("2024-10-17T10:15:00+09:00"));
+    }
+
+    #[test]
+    fn new_york_follows_daylight_saving() {
+        // 2024-12-02 17:00 EST
+        assert_eq!(resolve("16:00", &Market::NEW_YORK, "2024-12-02T22:00:00Z").as_deref(), Some("2024-12-02T16:00:00-05:00"));
+        // 日本時間ではすでに翌日でも、現地の日付で解釈する
+        assert_eq!(resolve("16:00", &Market::NEW_YORK, "2024-07-01T21:00:00Z").as_deref(), Some("2024-07-01T16:00:00-04:00"));
+    }
+
+    #[test]
+    fn unparseable_values_are_none() {
+        let now = "2024-10-17T06:05:00Z";
+        assert_eq!(resolve("N/A", &Market::TOKYO, now), None);
+        assert_eq!(resolve("", &Market::TOKYO, now), None);
+        assert_eq!(resolve("25:00", &Market::TOKYO, now), None);
+        assert_eq!(resolve("13/45", &Market::TOKYO, now), None);
+    }
+
+    #[test]
+    fn market_for_code() {
+        assert_eq!(Market::for_code("^DJI"), Some(Market::NEW_YORK));
+        assert_eq!(Market::for_code("^N225"), Some(Market::TOKYO));
+        assert_eq!(Market::for_code("^hsi"), Some(Market::HONG_KONG));
+        assert_eq!(Market::for_code("7203.T"), Some(Market::TOKYO));
+        assert_eq!(Market::for_code("998407.O"), Some(Market::TOKYO));
+        assert_eq!(Market::for_code("130A.T"), Some(Market::TOKYO));
+        assert_eq!(Market::for_code("USDJPY=X"), Some(Market::FX));
+        assert_eq!(Market::for_code("SONY"), Some(Market::US_LISTED));
+        assert_eq!(Market::for_code("BRK.B"), Some(Market::US_LISTED));
+        // 表にない指数はニューヨークとみなさない
+        assert_eq!(Market::for_code("^XYZ"), None);
+        // 形の分からないコードは東京とみなさない
+        assert_eq!(Market::for_code("!?"), None);
+        assert_eq!(Market::for_code(""), None);
+    }
+
+    #[test]
+    fn market_hours() {
+        // 2024-10-17 (木) 10:00 JST
+        assert!(Market::TOKYO.is_open(utc("2024-10-17T01:00:00Z")));
+        // 昼休み 12:00 JST
+        assert!(!Market::TOKYO.is_open(utc("2024-10-17T03:00:00Z")));
+        // 大引け 15:30 JST 以降
+        assert!(!Market::TOKYO.is_open(utc("2024-10-17T06:30:00Z")));
+        // 土曜日
+        assert!(!Market::TOKYO.is_open(utc("2024-10-19T01:00:00Z")));
+        // 2024-10-17 10:00 EDT
+        assert!(Market::NEW_YORK.is_open(utc("2024-10-17T14:00:00Z")));
+        assert!(!Market::NEW_YORK.is_open(utc("2024-10-17T01:00:00Z")));
+        // 為替は平日なら深夜でも開いている
+        assert!(Market::FX.is_open(utc("2024-10-17T15:00:00Z")));
+        assert!(!Market::FX.is_open(utc("2024-10-19T15:00:00Z")));
+        // 香港の昼休み (12:30 HKT) と後場 (13:30 HKT)
+        assert!(!Market::HONG_KONG.is_open(utc("2024-10-17T04:30:00Z")));
+        assert!(Market::HONG_KONG.is_open(utc("2024-10-17T05:30:00Z")));
+    }
+
+    #[test]
+    fn next_session_start() {
+        // 08:59 JST → 同日 09:00、昼休み → 12:30、金曜の引け後 → 月曜 09:00
+        assert_eq!(Market::TOKYO.next_open(utc("2024-10-16T23:59:00Z")), Some(utc("2024-10-17T00:00:00Z")));
+        assert_eq!(Market::TOKYO.next_open(utc("2024-10-17T03:00:00Z")), Some(utc("2024-10-17T03:30:00Z")));
+        assert_eq!(Market::TOKYO.next_open(utc("2024-10-18T07:00:00Z")), Some(utc("2024-10-21T00:00:00Z")));
+        // ニューヨークは現地の夏時間で 09:30
+        assert_eq!(Market::NEW_YORK.next_open(utc("2024-10-17T01:00:00Z")), Some(utc("2024-10-17T13:30:00Z")));
+    }
+
+    #[test]
+    fn fx_trades_from_sunday_to_friday_new_york_time() {
+        // 金曜 16:59 EDT (土曜 05:59 JST) はまだ開いていて、17:00 で閉まる
+        assert!(Market::FX.is_open(utc("2024-10-18T20:59:00Z")));
+        assert!(!Market::FX.is_open(utc("2024-10-18T21:00:00Z")));
+        // 日曜 16:59 EDT (月曜 05:59 JST) はまだ閉まっていて、17:00 に開く
+        assert!(!Market::FX.is_open(utc("2024-10-20T20:59:00Z")));
+        assert!(Market::FX.is_open(utc("2024-10-20T21:00:00Z")));
+        // 冬時間は 17:00 EST = 22:00 UTC
+        assert!(!Market::FX.is_open(utc("2024-12-01T21:30:00Z")));
+        assert!(Market::FX.is_open(utc("2024-12-01T22:00:00Z")));
+        // 週末に閉まっている間の次の取引開始は日曜 17:00
+        assert_eq!(Market::FX.next_open(utc("2024-10-19T03:00:00Z")), Some(utc("2024-10-20T21:00:00Z")));
+    }
+
+    #[test]
+    fn us_stocks_trade_in_new_york_but_display_japan_time() {
+        let sony = Market::for_code("SONY").unwrap();
+        // 2024-10-17 (木) 11:00 EDT は開いている (東京は引け後)
+        assert!(sony.is_open(utc("2024-10-17T15:00:00Z")));
+        assert!(!Market::TOKYO.is_open(utc("2024-10-17T15:00:00Z")));
+        // sample.html の "10/31 9:04" は日本時間
+        assert_eq!(resolve("10/31 9:04", &sony, "2025-10-31T01:00:00Z").as_deref(), Some("2025-10-31T09:04:00+09:00"));
+    }
+
+    #[test]
+    fn non_us_indices_use_their_own_timezone() {
+        let nikkei = Market::for_code("^N225").unwrap();
+        assert_eq!(resolve("10/17", &nikkei, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T15:30:00+09:00"));
+        let ftse = Market::for_code("^FTSE").unwrap();
+        assert_eq!(resolve("10/17", &ftse, "2024-10-19T01:00:00Z").as_deref(), Some("2024-10-17T16:30:00+01:00"));
+    }
+}
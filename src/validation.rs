@@ -0,0 +1,234 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::parsing::QuoteValues;
+
+// --- 価格・前日比・前日比率の整合性チェック ---
+// 前日終値 = price - change_abs を求め、そこから計算した騰落率が change_pct と丸めの範囲で一致するか、
+// change_abs と change_pct の符号がそろっているかを確かめる。出来高を価格と取り違えた場合などはここで分かる。
+
+// 照合できなかった (相手の値がない) 項目の信頼度
+const UNVERIFIED: f64 = 0.5;
+// 候補の組み合わせを試す深さ (各項目の上位何件まで)
+const RERANK_DEPTH: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FieldConfidence {
+    pub price: f64,
+    pub change_abs: f64,
+    pub change_pct: f64,
+}
+
+impl Default for FieldConfidence {
+    fn default() -> Self {
+        FieldConfidence { price: 1.0, change_abs: 1.0, change_pct: 1.0 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QuoteValidation {
+    // price - change_abs
+    pub previous_close: Option<Decimal>,
+    // change_abs / previous_close * 100 (小数4桁)
+    pub implied_pct: Option<Decimal>,
+    // 0.0〜1.0
+    pub confidence: FieldConfidence,
+    pub warnings: Vec<String>,
+}
+
+impl QuoteValidation {
+    pub fn is_consistent(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// values の3項目を突き合わせる。page_previous_close はページの「前日終値」欄 (あれば導出値とも比べる)
+pub fn validate(values: &QuoteValues, page_previous_close: Option<Decimal>) -> QuoteValidation {
+    let mut result = QuoteValidation::default();
+    let confidence = &mut result.confidence;
+    let warnings = &mut result.warnings;
+
+    for (name, value, slot) in [
+        ("price", values.price, &mut confidence.price),
+        ("change_abs", values.change_abs, &mut confidence.change_abs),
+        ("change_pct", values.change_pct, &mut confidence.change_pct),
+    ] {
+        if value.is_none() {
+            *slot = 0.0;
+            warnings.push(format!("{} is missing or not a number", name));
+        }
+    }
+
+    if let (Some(abs), Some(pct)) = (values.change_abs, values.change_pct) {
+        if !abs.is_zero() && !pct.is_zero() && abs.is_sign_negative() != pct.is_sign_negative() {
+            confidence.change_abs *= 0.5;
+            confidence.change_pct *= 0.5;
+            warnings.push(format!("change_abs {} and change_pct {}% have opposite signs", abs, pct));
+        }
+    }
+
+    let (Some(price), Some(abs)) = (values.price, values.change_abs) else {
+        // 前日終値を導けないので、残った項目は照合できていない
+        for slot in [&mut confidence.price, &mut confidence.change_abs, &mut confidence.change_pct] {
+            *slot = slot.min(UNVERIFIED);
+        }
+        return round_confidence(result);
+    };
+
+    let previous_close = price - abs;
+    result.previous_close = Some(previous_close);
+    if previous_close <= Decimal::ZERO {
+        confidence.price *= 0.3;
+        confidence.change_abs *= 0.5;
+        warnings.push(format!("Derived previous close {} is not positive", previous_close));
+        return round_confidence(result);
+    }
+
+    let implied = (abs / previous_close * Decimal::ONE_HUNDRED).round_dp(4);
+    result.implied_pct = Some(implied);
+    match values.change_pct {
+        Some(pct) => {
+            if (implied - pct).abs() > pct_tolerance(abs, pct, previous_close) {
+                // 価格を取り違えると導出した騰落率が桁違いになるので、価格を最も疑う
+                confidence.price *= 0.4;
+                confidence.change_abs *= 0.7;
+                confidence.change_pct *= 0.7;
+                warnings.push(format!("change_pct {}% differs from {}% implied by price {} and change_abs {}", pct, implied, price, abs));
+            }
+        }
+        None => {
+            confidence.price = confidence.price.min(UNVERIFIED);
+            confidence.change_abs = confidence.change_abs.min(UNVERIFIED);
+        }
+    }
+
+    if let Some(page) = page_previous_close.filter(|p| *p > Decimal::ZERO) {
+        if (page - previous_close).abs() > pct_tolerance_abs(abs) {
+            confidence.price *= 0.5;
+            confidence.change_abs *= 0.5;
+            warnings.push(format!("Derived previous close {} differs from the page's previous close {}", previous_close, page));
+        }
+    }
+    round_confidence(result)
+}
+
+// 表示の丸め (change_abs・change_pct は表示桁で丸められている) で生じうる差に 0.01 ポイントの余裕を足す
+fn pct_tolerance(abs: Decimal, pct: Decimal, previous_close: Decimal) -> Decimal {
+    let from_abs = half_ulp(abs) / previous_close * Decimal::ONE_HUNDRED;
+    from_abs + half_ulp(pct) + Decimal::new(1, 2)
+}
+
+// 前日終値同士の比較は change_abs の表示桁の丸めだけ許す
+fn pct_tolerance_abs(abs: Decimal) -> Decimal {
+    half_ulp(abs) * Decimal::TWO
+}
+
+// 表示の最小桁の半分 (2.50 なら 0.005)。小数が Decimal の上限 (28 桁) まである値は 28 桁目で打ち切る
+fn half_ulp(value: Decimal) -> Decimal {
+    Decimal::try_new(5, (value.scale() + 1).min(Decimal::MAX_SCALE)).unwrap_or(Decimal::ZERO)
+}
+
+fn round_confidence(mut result: QuoteValidation) -> QuoteValidation {
+    let round = |v: f64| (v * 100.0).round() / 100.0;
+    let c = &mut result.confidence;
+    c.price = round(c.price);
+    c.change_abs = round(c.change_abs);
+    c.change_pct = round(c.change_pct);
+    result
+}
+
+/// 各項目の上位候補から、整合性チェックを通る組み合わせを選ぶ (順位の和が小さいものを優先)。
+/// どれも通らなければ各項目の1位。どれかの候補が空なら None
+pub fn most_consistent(prices: &[&str], changes_abs: &[&str], changes_pct: &[&str]) -> Option<(usize, usize, usize)> {
+    if prices.is_empty() || changes_abs.is_empty() || changes_pct.is_empty() {
+        return None;
+    }
+    let mut combos = Vec::new();
+    for (i, price) in prices.iter().take(RERANK_DEPTH).enumerate() {
+        for (j, abs) in changes_abs.iter().take(RERANK_DEPTH).enumerate() {
+            for (k, pct) in changes_pct.iter().take(RERANK_DEPTH).enumerate() {
+                combos.push(((i + j + k, i, j, k), (*price, *abs, *pct)));
+            }
+        }
+    }
+    combos.sort_by_key(|(rank, _)| *rank);
+    let best = combos
+        .into_iter()
+        .find(|(_, (price, abs, pct))| validate(&QuoteValues::from_display(price, abs, pct), None).is_consistent())
+        .map_or((0, 0, 0), |((_, i, j, k), _)| (i, j, k));
+    Some(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    fn check(price: &str, abs: &str, pct: &str) -> QuoteValidation {
+        validate(&QuoteValues::from_display(price, abs, pct), None)
+    }
+
+    #[test]
+    fn consistent_quotes() {
+        let v = check("27.75", "-0.43", "(-1.53%)");
+        assert!(v.is_consistent(), "{:?}", v.warnings);
+        assert_eq!(v.previous_close, Some(dec("28.18")));
+        assert_eq!(v.implied_pct, Some(dec("-1.5259")));
+        assert_eq!(v.confidence, FieldConfidence::default());
+        assert!(check("2,862.5", "+12.5", "+0.44%").is_consistent());
+        assert!(check("100", "0", "0.00%").is_consistent());
+    }
+
+    #[test]
+    fn volume_taken_for_price() {
+        let v = check("6,245,778", "-0.43", "-1.53%");
+        assert_eq!(v.warnings.len(), 1);
+        assert!(v.warnings[0].starts_with("change_pct -1.53% differs"));
+        assert!(v.confidence.price < v.confidence.change_pct);
+    }
+
+    #[test]
+    fn opposite_signs_and_missing_values() {
+        let v = check("27.75", "+0.43", "-1.53%");
+        assert!(v.warnings.iter().any(|w| w.contains("opposite signs")));
+
+        let v = check("27.75", "---", "-1.53%");
+        assert_eq!(v.warnings, vec!["change_abs is missing or not a number".to_string()]);
+        assert_eq!(v.confidence, FieldConfidence { price: UNVERIFIED, change_abs: 0.0, change_pct: UNVERIFIED });
+
+        let v = check("0.5", "+3", "+1%");
+        assert!(v.warnings.iter().any(|w| w.contains("not positive")));
+    }
+
+    #[test]
+    fn values_at_maximum_scale_do_not_panic() {
+        // parse_decimal は 28 桁を超える小数を 28 桁に丸める
+        let long = format!("+0.{}", "1".repeat(30));
+        let values = QuoteValues::from_display("100", &long, "+0.11%");
+        assert_eq!(values.change_abs.map(|d| d.scale()), Some(Decimal::MAX_SCALE));
+        assert_eq!(half_ulp(values.change_abs.unwrap()), Decimal::new(5, Decimal::MAX_SCALE));
+        assert!(validate(&values, None).is_consistent());
+    }
+
+    #[test]
+    fn page_previous_close_is_compared() {
+        let values = QuoteValues::from_display("27.75", "-0.43", "-1.53%");
+        assert!(validate(&values, Some(dec("28.18"))).is_consistent());
+        let v = validate(&values, Some(dec("30.00")));
+        assert!(v.warnings[0].contains("previous close 30.00"));
+    }
+
+    #[test]
+    fn candidates_are_reranked_by_consistency() {
+        // 1位の価格は出来高の取り違え、2位が正しい
+        let picked = most_consistent(&["6,245,778", "27.75"], &["-0.43"], &["(-1.53%)", "-1.53"]);
+        assert_eq!(picked, Some((1, 0, 0)));
+        // どの組み合わせも通らなければ1位のまま
+        assert_eq!(most_consistent(&["1"], &["+5"], &["-1%"]), Some((0, 0, 0)));
+        assert_eq!(most_consistent(&[], &["+5"], &["1%"]), None);
+    }
+}